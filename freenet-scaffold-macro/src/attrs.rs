@@ -0,0 +1,234 @@
+use quote::format_ident;
+use syn::meta::ParseNestedMeta;
+use syn::punctuated::Punctuated;
+use syn::{parse_quote, DeriveInput, Ident, LitInt, LitStr, Path, Token, Type, Visibility};
+
+/// The arguments of `#[composable(...)]` on the type itself, which configure the generated
+/// Summary and Delta types.
+pub(crate) struct ContainerAttrs {
+    pub(crate) summary: GeneratedType,
+    pub(crate) delta: GeneratedType,
+    /// `invertible`: also implement InvertibleState, which every field must implement.
+    pub(crate) invertible: bool,
+    /// `version = N`: implement Versioned with this schema version.
+    pub(crate) version: Option<LitInt>,
+    /// `previous = "Type"`: the version this type replaces, which implements Migrate.
+    pub(crate) previous: Option<Type>,
+}
+
+/// How a generated Summary or Delta type is declared.
+pub(crate) struct GeneratedType {
+    pub(crate) name: Ident,
+    pub(crate) vis: Visibility,
+    /// The derives ComposableState requires, followed by those from `summary_derive(..)` or
+    /// `delta_derive(..)`
+    pub(crate) derives: Vec<Path>,
+}
+
+impl ContainerAttrs {
+    /// The defaults for a type named `name`: public `{name}Summary` and `{name}Delta` types.
+    pub(crate) fn new(name: &Ident) -> Self {
+        let derives: Vec<Path> = vec![
+            parse_quote!(serde::Serialize),
+            parse_quote!(serde::Deserialize),
+            parse_quote!(Clone),
+            parse_quote!(PartialEq),
+            parse_quote!(Debug),
+        ];
+        ContainerAttrs {
+            summary: GeneratedType {
+                name: format_ident!("{}Summary", name),
+                vis: parse_quote!(pub),
+                derives: derives.clone(),
+            },
+            delta: GeneratedType {
+                name: format_ident!("{}Delta", name),
+                vis: parse_quote!(pub),
+                derives,
+            },
+            invertible: false,
+            version: None,
+            previous: None,
+        }
+    }
+
+    pub(crate) fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("invertible") {
+            self.invertible = true;
+            return Ok(());
+        }
+        if meta.path.is_ident("version") {
+            let version: LitInt = meta.value()?.parse()?;
+            if version.base10_parse::<u32>()? == 0 {
+                return Err(syn::Error::new_spanned(version, "versions start at 1"));
+            }
+            self.version = Some(version);
+            return Ok(());
+        }
+        if meta.path.is_ident("previous") {
+            self.previous = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+            return Ok(());
+        }
+        let (generated, setting) = match meta.path.get_ident().map(Ident::to_string) {
+            Some(arg) if arg.starts_with("summary_") => {
+                (&mut self.summary, arg["summary_".len()..].to_string())
+            }
+            Some(arg) if arg.starts_with("delta_") => {
+                (&mut self.delta, arg["delta_".len()..].to_string())
+            }
+            _ => return Err(meta.error(UNKNOWN_CONTAINER_ARG)),
+        };
+        match setting.as_str() {
+            "derive" => {
+                let content;
+                syn::parenthesized!(content in meta.input);
+                for derive in Punctuated::<Path, Token![,]>::parse_terminated(&content)? {
+                    generated.add_derive(derive);
+                }
+            }
+            "vis" => generated.vis = meta.value()?.parse::<LitStr>()?.parse()?,
+            "name" => generated.name = meta.value()?.parse::<LitStr>()?.parse()?,
+            _ => return Err(meta.error(UNKNOWN_CONTAINER_ARG)),
+        }
+        Ok(())
+    }
+
+    /// Fails if a generated type is less visible than `input`, whose ComposableState
+    /// implementation names it, so the user gets this error instead of E0446.
+    pub(crate) fn check_visibility(&self, input: &DeriveInput) -> syn::Result<()> {
+        for (generated, setting) in [(&self.summary, "summary_vis"), (&self.delta, "delta_vis")] {
+            if scope(&generated.vis) < scope(&input.vis) {
+                return Err(syn::Error::new_spanned(
+                    &generated.vis,
+                    format!(
+                        "`{}` can't be narrower than the visibility of `{}`, whose ComposableState implementation names the generated type",
+                        setting, input.ident
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Orders visibilities by how widely they are visible. `pub(super)` and `pub(in path)` are
+/// only compared with `pub`, `pub(crate)` and private, anything finer is left to the compiler.
+fn scope(vis: &Visibility) -> u8 {
+    match vis {
+        Visibility::Public(_) => 3,
+        Visibility::Restricted(restricted) if restricted.path.is_ident("crate") => 2,
+        Visibility::Restricted(restricted) if restricted.path.is_ident("self") => 0,
+        Visibility::Restricted(_) => 1,
+        Visibility::Inherited => 0,
+    }
+}
+
+const UNKNOWN_CONTAINER_ARG: &str =
+    "expected `invertible`, `version`, `previous`, `summary_derive(..)`, `delta_derive(..)`, `summary_vis`, `delta_vis`, `summary_name` or `delta_name`";
+
+impl GeneratedType {
+    /// Adds a derive unless one with the same name is already there.
+    pub(crate) fn add_derive(&mut self, derive: Path) {
+        let name = |path: &Path| path.segments.last().map(|segment| segment.ident.clone());
+        if !self
+            .derives
+            .iter()
+            .any(|existing| name(existing) == name(&derive))
+        {
+            self.derives.push(derive);
+        }
+    }
+}
+
+/// How a struct field takes part in the generated ComposableState implementation.
+pub(crate) enum FieldKind {
+    /// The field implements ComposableState and is part of the summary and delta.
+    Composable,
+    /// `#[composable(skip)]`: local-only data the generated code never touches.
+    Skip,
+    /// `#[composable(derived = "fn")]`: local-only data set to `fn(&self)` after every
+    /// `apply_delta`.
+    Derived(Path),
+}
+
+pub(crate) struct FieldAttrs {
+    pub(crate) kind: FieldKind,
+    /// `#[composable(rename = "name")]`: the name of the field in the Summary and Delta.
+    pub(crate) rename: Option<Ident>,
+    /// `#[composable(after = "a, b")]`: fields that must be verified and updated before this
+    /// one, by their name in the struct (or index, for tuple structs).
+    pub(crate) after: Vec<LitStr>,
+}
+
+/// Parses the `#[composable(...)]` attributes of a struct field.
+pub(crate) fn field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs {
+        kind: FieldKind::Composable,
+        rename: None,
+        after: Vec::new(),
+    };
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("composable"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                set_kind(&mut attrs.kind, FieldKind::Skip, &meta)
+            } else if meta.path.is_ident("derived") {
+                let function: LitStr = meta.value()?.parse()?;
+                set_kind(
+                    &mut attrs.kind,
+                    FieldKind::Derived(function.parse()?),
+                    &meta,
+                )
+            } else if meta.path.is_ident("rename") {
+                if field.ident.is_none() {
+                    return Err(meta.error("`rename` only applies to named fields"));
+                }
+                let name: LitStr = meta.value()?.parse()?;
+                attrs.rename = Some(name.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("after") {
+                let fields: LitStr = meta.value()?.parse()?;
+                for name in fields.value().split(',').map(str::trim) {
+                    attrs.after.push(LitStr::new(name, fields.span()));
+                }
+                Ok(())
+            } else {
+                Err(meta.error(
+                    "expected `skip`, `derived = \"fn\"`, `rename = \"name\"` or `after = \"field\"`",
+                ))
+            }
+        })?;
+    }
+    if (attrs.rename.is_some() || !attrs.after.is_empty())
+        && !matches!(attrs.kind, FieldKind::Composable)
+    {
+        return Err(syn::Error::new_spanned(
+            field,
+            "`rename` and `after` have no effect on a field that isn't part of the summary and delta",
+        ));
+    }
+    Ok(attrs)
+}
+
+fn set_kind(kind: &mut FieldKind, new_kind: FieldKind, meta: &ParseNestedMeta) -> syn::Result<()> {
+    if !matches!(kind, FieldKind::Composable) {
+        return Err(meta.error("a field can't be both `skip` and `derived`"));
+    }
+    *kind = new_kind;
+    Ok(())
+}
+
+/// Removes the `#[composable(...)]` field attributes, which the compiler would otherwise try to
+/// expand as attribute macros on the emitted struct.
+pub(crate) fn strip_field_attrs(input: &mut DeriveInput) {
+    if let syn::Data::Struct(data_struct) = &mut input.data {
+        for field in data_struct.fields.iter_mut() {
+            field
+                .attrs
+                .retain(|attr| !attr.path().is_ident("composable"));
+        }
+    }
+}
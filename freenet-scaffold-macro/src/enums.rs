@@ -0,0 +1,327 @@
+use crate::attrs::ContainerAttrs;
+use crate::{
+    impl_where_clause, invertible_where_clause, type_checks, types_where_clause, versioned_impl,
+};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DataEnum, DeriveInput, Fields, Type};
+
+/// Variants are either unit variants or wrap a single ComposableState value.
+struct Variant<'a> {
+    ident: &'a syn::Ident,
+    ty: Option<&'a Type>,
+}
+
+/// Enums are treated as state machines whose variants are ordered by declaration. The summary
+/// and delta are tagged by variant, and moving to a later variant is expressed as a delta that
+/// replaces the whole state. A state never moves back to an earlier variant, so two peers that
+/// concurrently move to different variants both end up in the later one.
+pub(crate) fn expand(
+    input: &DeriveInput,
+    data_enum: &DataEnum,
+    attrs: ContainerAttrs,
+) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+
+    let variants = data_enum
+        .variants
+        .iter()
+        .map(|variant| {
+            if variant.ident == "Replace" {
+                return Err(syn::Error::new_spanned(
+                    &variant.ident,
+                    "`Replace` is reserved for the generated delta of a #[composable] enum",
+                ));
+            }
+            let ty = match &variant.fields {
+                Fields::Unit => None,
+                Fields::Unnamed(fields) if fields.unnamed.len() == 1 => Some(&fields.unnamed[0].ty),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        variant,
+                        "variants of a #[composable] enum must be unit variants or wrap a single ComposableState value",
+                    ))
+                }
+            };
+            Ok(Variant {
+                ident: &variant.ident,
+                ty,
+            })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let variant_types: Vec<&Type> = variants.iter().filter_map(|variant| variant.ty).collect();
+    // Take the type of the first variant with a value to derive ParentState and Parameters
+    let Some(first_variant_type) = variant_types.first().copied() else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "a #[composable] enum needs at least one variant wrapping a ComposableState value",
+        ));
+    };
+
+    let summary_name = &attrs.summary.name;
+    let delta_name = &attrs.delta.name;
+
+    let summary_variants = variants.iter().map(|Variant { ident, ty }| match ty {
+        Some(ty) => quote! { #ident(<#ty as freenet_scaffold::ComposableState>::Summary) },
+        None => quote! { #ident },
+    });
+
+    let delta_variants = variants.iter().filter_map(|Variant { ident, ty }| {
+        ty.map(|ty| quote! { #ident(<#ty as freenet_scaffold::ComposableState>::Delta) })
+    });
+
+    let state_patterns: Vec<TokenStream> = variants
+        .iter()
+        .map(|Variant { ident, ty }| match ty {
+            Some(_) => quote! { #name::#ident(_) },
+            None => quote! { #name::#ident },
+        })
+        .collect();
+    let summary_patterns = variants.iter().map(|Variant { ident, ty }| match ty {
+        Some(_) => quote! { #summary_name::#ident(_) },
+        None => quote! { #summary_name::#ident },
+    });
+    let indices: Vec<usize> = (0..variants.len()).collect();
+
+    let verify_impl = variants.iter().map(|Variant { ident, ty }| {
+        let path = ident.to_string();
+        match ty {
+            Some(ty) => quote! {
+                #name::#ident(value) => <#ty as freenet_scaffold::ComposableState>::verify(value, parent_state, parameters)
+                    .map_err(|e| e.in_field(#path)),
+            },
+            None => quote! { #name::#ident => Ok(()), },
+        }
+    });
+
+    let summarize_impl = variants.iter().map(|Variant { ident, ty }| match ty {
+        Some(ty) => quote! {
+            #name::#ident(value) => #summary_name::#ident(
+                <#ty as freenet_scaffold::ComposableState>::summarize(value, parent_state, parameters)
+            ),
+        },
+        None => quote! { #name::#ident => #summary_name::#ident, },
+    });
+
+    let delta_impl = variants.iter().map(|Variant { ident, ty }| match ty {
+        Some(ty) => quote! {
+            (#name::#ident(value), #summary_name::#ident(old)) =>
+                <#ty as freenet_scaffold::ComposableState>::delta(value, parent_state, parameters, old)
+                    .map(#delta_name::#ident),
+        },
+        None => quote! { (#name::#ident, #summary_name::#ident) => None, },
+    });
+
+    // Note: like the fields of a struct, the value is passed a clone of self as its parent_state
+    let apply_delta_impl = variants
+        .iter()
+        .enumerate()
+        .filter_map(|(index, Variant { ident, ty })| {
+            let path = ident.to_string();
+            ty.map(|ty| {
+                quote! {
+                    Some(#delta_name::#ident(variant_delta)) => {
+                        let self_clone = self.clone();
+                        match self {
+                            #name::#ident(value) => <#ty as freenet_scaffold::ComposableState>::apply_delta(
+                                value,
+                                &self_clone,
+                                parameters,
+                                &Some(variant_delta.clone()),
+                            )
+                            .map_err(|e| e.in_field(#path)),
+                            // The state has since moved on to a later variant
+                            _ if self_clone.__composable_variant_index() > #index => Ok(()),
+                            _ => Err(freenet_scaffold::ScaffoldError::invalid_delta(
+                                "delta is for a later variant than the current state",
+                            )
+                            .in_field(#path)),
+                        }
+                    }
+                }
+            })
+        });
+
+    let compose_impl = variants.iter().filter_map(|Variant { ident, ty }| {
+        ty.map(|ty| {
+            quote! {
+                (#delta_name::#ident(first), #delta_name::#ident(second)) =>
+                    <#ty as freenet_scaffold::ComposableState>::compose_deltas(first, second)
+                        .map(#delta_name::#ident),
+            }
+        })
+    });
+
+    let generic = !input.generics.params.is_empty();
+    let checks = if generic {
+        // The checks can't name generic parameters, the impl's where clause covers them instead
+        TokenStream::new()
+    } else {
+        type_checks(&variant_types, first_variant_type)
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let summary_where = types_where_clause(
+        &input.generics,
+        &variant_types,
+        "Summary",
+        &attrs.summary.derives,
+    );
+    let delta_where = types_where_clause(
+        &input.generics,
+        &variant_types,
+        "Delta",
+        &attrs.delta.derives,
+    );
+    let (summary_vis, summary_derives) = (&attrs.summary.vis, &attrs.summary.derives);
+    let (delta_vis, delta_derives) = (&attrs.delta.vis, &attrs.delta.derives);
+    let impl_where = impl_where_clause(
+        input,
+        &variant_types,
+        first_variant_type,
+        &[&summary_where, &delta_where],
+    );
+    let serde_bound = if generic {
+        quote! { #[serde(bound = "")] }
+    } else {
+        TokenStream::new()
+    };
+
+    // Only a nested delta for the current variant can be undone, states never move back
+    let invertible_impl = if attrs.invertible {
+        let invert_impl = variants.iter().filter_map(|Variant { ident, ty }| {
+            let path = ident.to_string();
+            ty.map(|ty| {
+                quote! {
+                    (#name::#ident(value), #delta_name::#ident(variant_delta)) =>
+                        <#ty as freenet_scaffold::InvertibleState>::invert_delta(value, parent_state, parameters, variant_delta)
+                            .map(#delta_name::#ident)
+                            .map_err(|e| e.in_field(#path)),
+                }
+            })
+        });
+        let invertible_where = invertible_where_clause(&impl_where, &variant_types);
+        quote! {
+            impl #impl_generics freenet_scaffold::InvertibleState for #name #ty_generics #invertible_where {
+                fn invert_delta(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters, delta: &Self::Delta) -> Result<Self::Delta, freenet_scaffold::ScaffoldError> {
+                    // The last arm is unreachable for an enum with a single variant
+                    #[allow(unreachable_patterns)]
+                    match (self, delta) {
+                        #(#invert_impl)*
+                        (_, #delta_name::Replace(_)) => Err(freenet_scaffold::ScaffoldError::invalid_delta(
+                            "a state can't move back to an earlier variant",
+                        )),
+                        _ => Err(freenet_scaffold::ScaffoldError::invalid_delta(
+                            "delta is not for the current variant",
+                        )),
+                    }
+                }
+            }
+        }
+    } else {
+        TokenStream::new()
+    };
+
+    let versioned_impl = versioned_impl(input, &attrs, &impl_where);
+
+    let expanded = quote! {
+        #input
+
+        #[derive(#(#summary_derives),*)]
+        #serde_bound
+        #summary_vis enum #summary_name #impl_generics #summary_where {
+            #(#summary_variants,)*
+        }
+
+        #[derive(#(#delta_derives),*)]
+        #serde_bound
+        #delta_vis enum #delta_name #impl_generics #delta_where {
+            #(#delta_variants,)*
+            /// Replaces the whole state, used when it has moved to a later variant.
+            Replace(#name #ty_generics),
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            #[doc(hidden)]
+            fn __composable_variant_index(&self) -> usize {
+                match self {
+                    #(#state_patterns => #indices,)*
+                }
+            }
+        }
+
+        impl #impl_generics freenet_scaffold::ComposableState for #name #ty_generics #impl_where {
+            type ParentState = #name #ty_generics;
+            type Summary = #summary_name #ty_generics;
+            type Delta = #delta_name #ty_generics;
+            type Parameters = <#first_variant_type as freenet_scaffold::ComposableState>::Parameters;
+
+            fn verify(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters) -> Result<(), freenet_scaffold::ScaffoldError> {
+                match self {
+                    #(#verify_impl)*
+                }
+            }
+
+            fn summarize(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters) -> Self::Summary {
+                match self {
+                    #(#summarize_impl)*
+                }
+            }
+
+            fn delta(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters, old_state_summary: &Self::Summary) -> Option<Self::Delta> {
+                let old_index = match old_state_summary {
+                    #(#summary_patterns => #indices,)*
+                };
+                let index = self.__composable_variant_index();
+                if index > old_index {
+                    return Some(#delta_name::Replace(self.clone()));
+                }
+                match (self, old_state_summary) {
+                    #(#delta_impl)*
+                    // The other side has moved to a later variant, it has nothing to learn from us
+                    _ => None,
+                }
+            }
+
+            // parent_state disregarded because we need to use self so that dependencies between fields work, ugly
+            fn apply_delta(&mut self, _parent_state: &Self::ParentState, parameters: &Self::Parameters, delta: &Option<Self::Delta>) -> Result<(), freenet_scaffold::ScaffoldError> {
+                match delta {
+                    None => Ok(()),
+                    Some(#delta_name::Replace(state)) => {
+                        // Replacements with an earlier or the same variant are stale
+                        if state.__composable_variant_index() > self.__composable_variant_index() {
+                            *self = state.clone();
+                        }
+                        Ok(())
+                    }
+                    #(#apply_delta_impl)*
+                }
+            }
+
+            fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+                match (first, second) {
+                    // Only the later of two replacements can take effect
+                    (#delta_name::Replace(first_state), #delta_name::Replace(second_state)) => {
+                        let later = if second_state.__composable_variant_index() > first_state.__composable_variant_index() {
+                            second_state
+                        } else {
+                            first_state
+                        };
+                        Some(#delta_name::Replace(later.clone()))
+                    }
+                    #(#compose_impl)*
+                    // Whether a replacement wipes out a nested delta depends on the state
+                    _ => None,
+                }
+            }
+        }
+
+        #invertible_impl
+
+        #versioned_impl
+
+        #checks
+    };
+
+    Ok(expanded)
+}
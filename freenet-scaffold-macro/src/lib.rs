@@ -0,0 +1,272 @@
+extern crate proc_macro;
+
+mod attrs;
+mod enums;
+mod structs;
+
+use attrs::ContainerAttrs;
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Fields, Generics, Path, Type, WhereClause,
+};
+
+/// Implements `ComposableState` for a struct whose fields all implement it, or an enum whose
+/// variants hold such fields, generating `{Name}Summary` and `{Name}Delta` types.
+///
+/// Arguments on the type:
+///
+/// - `summary_derive(..)` and `delta_derive(..)` add derives to the generated types.
+/// - `summary_vis = ".."` and `delta_vis = ".."` set the visibility of the generated types and
+///   their fields, `pub` by default. The `ComposableState` implementation names them, so they
+///   can't be narrower than the visibility of the type itself: on a `pub` struct,
+///   `delta_vis = "pub(crate)"` is an error, while on a `pub(crate)` struct it is allowed.
+/// - `summary_name = ".."` and `delta_name = ".."` replace the default names.
+/// - `invertible` also implements `InvertibleState`.
+/// - `version = N` and `previous = "Type"` implement `Versioned`, see `freenet_scaffold::schema`.
+///
+/// Fields take `skip`, `derived = "fn"`, `rename = "name"` and `after = "field"`.
+#[proc_macro_attribute]
+pub fn composable(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    let mut attrs = ContainerAttrs::new(&input.ident);
+    let attr_parser = syn::meta::parser(|meta| attrs.parse(meta));
+    parse_macro_input!(attr with attr_parser);
+    if let (None, Some(previous)) = (&attrs.version, &attrs.previous) {
+        return syn::Error::new_spanned(previous, "`previous` requires a `version`")
+            .to_compile_error()
+            .into();
+    }
+    if let Err(error) = attrs.check_visibility(&input) {
+        return error.to_compile_error().into();
+    }
+
+    let expanded = match &input.data {
+        Data::Struct(data_struct) => match &data_struct.fields {
+            Fields::Unit => Err(syn::Error::new_spanned(
+                &input.ident,
+                "ComposableState can't be applied to unit structs",
+            )),
+            fields => structs::expand(&input, fields, attrs),
+        },
+        Data::Enum(data_enum) => enums::expand(&input, data_enum, attrs),
+        Data::Union(_) => Err(syn::Error::new_spanned(
+            &input.ident,
+            "ComposableState can only be applied to structs and enums",
+        )),
+    };
+
+    match expanded {
+        Ok(expanded) => quote! {
+            // Underscore import so that several #[composable] types can share a module
+            use freenet_scaffold::ComposableState as _;
+
+            #expanded
+        }
+        .into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+/// Checks that provide better compile-time error messages when a field type doesn't implement
+/// ComposableState or doesn't share the ParentState and Parameters of the first field.
+fn type_checks(types: &[&Type], first_type: &Type) -> proc_macro2::TokenStream {
+    let check_composable_impls = types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_composable<T: freenet_scaffold::ComposableState>() {}
+                check_composable::<#ty>();
+            };
+        }
+    });
+
+    let check_matching_parent_state = types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_parent_state<T: freenet_scaffold::ComposableState<ParentState = <#first_type as freenet_scaffold::ComposableState>::ParentState>>() {}
+                check_parent_state::<#ty>();
+            };
+        }
+    });
+
+    let check_matching_parameters = types.iter().map(|ty| {
+        quote! {
+            const _: fn() = || {
+                fn check_parameters<T: freenet_scaffold::ComposableState<Parameters = <#first_type as freenet_scaffold::ComposableState>::Parameters>>() {}
+                check_parameters::<#ty>();
+            };
+        }
+    });
+
+    quote! {
+        #(#check_composable_impls)*
+        #(#check_matching_parent_state)*
+        #(#check_matching_parameters)*
+    }
+}
+
+/// The where clause of a generated Summary or Delta type: the original where clause plus, for
+/// generic types, a `ComposableState` bound on every field type.
+///
+/// Those bounds stop the compiler from normalizing `<Field as ComposableState>::Summary` to a
+/// concrete type inside derived impls, so each derived trait that isn't already implied by
+/// `ComposableState` gets an explicit bound on the associated type (`assoc`) too.
+fn types_where_clause(
+    generics: &Generics,
+    types: &[&Type],
+    assoc: &str,
+    derives: &[Path],
+) -> Option<WhereClause> {
+    if generics.params.is_empty() {
+        return generics.where_clause.clone();
+    }
+    let assoc = format_ident!("{}", assoc);
+    let mut generics = generics.clone();
+    let where_clause = generics.make_where_clause();
+    for ty in types {
+        where_clause
+            .predicates
+            .push(parse_quote! { #ty: freenet_scaffold::ComposableState });
+        for derive in derives
+            .iter()
+            .filter(|derive| !implied_by_composable_state(derive))
+        {
+            where_clause
+                .predicates
+                .push(parse_quote! { <#ty as freenet_scaffold::ComposableState>::#assoc: #derive });
+        }
+    }
+    Some(where_clause.clone())
+}
+
+/// Derives that every Summary and Delta type already supports, or that hold for `Option`.
+fn implied_by_composable_state(derive: &Path) -> bool {
+    let Some(last) = derive.segments.last() else {
+        return false;
+    };
+    ["Clone", "Debug", "Serialize", "Deserialize", "Default"]
+        .iter()
+        .any(|implied| last.ident == implied)
+}
+
+/// The where clause of the ComposableState impl. For generic types every field type must take
+/// the type itself as its ParentState and share the Parameters of the first field, which the
+/// checks from [`type_checks`] can't express, and the bounds of the generated types must hold.
+fn impl_where_clause(
+    input: &DeriveInput,
+    types: &[&Type],
+    first_type: &Type,
+    type_bounds: &[&Option<WhereClause>],
+) -> WhereClause {
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    let generic = !generics.params.is_empty();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    let where_clause = generics.make_where_clause();
+    for (index, ty) in types.iter().enumerate() {
+        where_clause.predicates.push(if !generic {
+            parse_quote! { #ty: freenet_scaffold::ComposableState }
+        } else if index == 0 {
+            parse_quote! { #ty: freenet_scaffold::ComposableState<ParentState = #name #ty_generics> }
+        } else {
+            parse_quote! {
+                #ty: freenet_scaffold::ComposableState<
+                    ParentState = #name #ty_generics,
+                    Parameters = <#first_type as freenet_scaffold::ComposableState>::Parameters,
+                >
+            }
+        });
+    }
+    if generic {
+        for bounds in type_bounds.iter().filter_map(|bounds| bounds.as_ref()) {
+            where_clause
+                .predicates
+                .extend(bounds.predicates.iter().cloned());
+        }
+    }
+    where_clause.clone()
+}
+
+/// The where clause of the InvertibleState impl generated for `#[composable(invertible)]`.
+fn invertible_where_clause(impl_where: &WhereClause, types: &[&Type]) -> WhereClause {
+    let mut where_clause = impl_where.clone();
+    for ty in types {
+        where_clause
+            .predicates
+            .push(parse_quote! { #ty: freenet_scaffold::InvertibleState });
+    }
+    where_clause
+}
+
+/// The Versioned impl generated for `#[composable(version = N)]`. Bodies written at an earlier
+/// version are read as the `previous` type and migrated, one version at a time.
+fn versioned_impl(
+    input: &DeriveInput,
+    attrs: &ContainerAttrs,
+    impl_where: &WhereClause,
+) -> proc_macro2::TokenStream {
+    let Some(version) = &attrs.version else {
+        return quote! {};
+    };
+    let name = &input.ident;
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+    let mut versioned_where = impl_where.clone();
+    if !input.generics.params.is_empty() {
+        versioned_where.predicates.push(
+            parse_quote! { #name #ty_generics: serde::Serialize + serde::de::DeserializeOwned },
+        );
+    }
+
+    let (read_state, read_summary, read_delta) = match &attrs.previous {
+        Some(previous) => {
+            versioned_where
+                .predicates
+                .push(parse_quote! { #previous: freenet_scaffold::schema::Migrate<Next = Self> });
+            let versioned = quote! { <#previous as freenet_scaffold::schema::Versioned> };
+            let migrate = quote! { <#previous as freenet_scaffold::schema::Migrate> };
+            (
+                quote! { #versioned::read_state(format, schema, body).map(#migrate::migrate_state) },
+                quote! { #versioned::read_summary(format, schema, body).map(#migrate::migrate_summary) },
+                quote! {
+                    #versioned::read_delta(format, schema, body)
+                        .map(|delta| delta.map(#migrate::migrate_delta))
+                },
+            )
+        }
+        None => {
+            let unsupported =
+                quote! { Err(freenet_scaffold::codec::CodecError::UnsupportedSchema(schema)) };
+            (unsupported.clone(), unsupported.clone(), unsupported)
+        }
+    };
+
+    quote! {
+        impl #impl_generics freenet_scaffold::schema::Versioned for #name #ty_generics #versioned_where {
+            const VERSION: u32 = #version;
+
+            fn read_state(
+                format: freenet_scaffold::codec::Format,
+                schema: u32,
+                body: &[u8],
+            ) -> Result<Self, freenet_scaffold::codec::CodecError> {
+                freenet_scaffold::schema::read_or_migrate(format, schema, #version, body, || #read_state)
+            }
+
+            fn read_summary(
+                format: freenet_scaffold::codec::Format,
+                schema: u32,
+                body: &[u8],
+            ) -> Result<Self::Summary, freenet_scaffold::codec::CodecError> {
+                freenet_scaffold::schema::read_or_migrate(format, schema, #version, body, || #read_summary)
+            }
+
+            fn read_delta(
+                format: freenet_scaffold::codec::Format,
+                schema: u32,
+                body: &[u8],
+            ) -> Result<Option<Self::Delta>, freenet_scaffold::codec::CodecError> {
+                freenet_scaffold::schema::read_or_migrate(format, schema, #version, body, || #read_delta)
+            }
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use crate::attrs::{field_attrs, strip_field_attrs, ContainerAttrs, FieldKind};
+use crate::{
+    impl_where_clause, invertible_where_clause, type_checks, types_where_clause, versioned_impl,
+};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, Fields, Member, Path, Type};
+
+struct Field<'a> {
+    /// `name` for named fields, `0`, `1`, ... for tuple struct fields
+    member: Member,
+    /// The field in the Summary and Delta, which differs from `member` when the field is renamed
+    /// or earlier fields of a tuple struct are skipped
+    synced_member: Member,
+    ty: &'a Type,
+    /// How the field appears in the path of a ScaffoldError
+    path: String,
+    /// Indices into the synced fields of the fields named by `#[composable(after = "..")]`
+    after: Vec<usize>,
+}
+
+pub(crate) fn expand(
+    input: &DeriveInput,
+    fields: &Fields,
+    mut attrs: ContainerAttrs,
+) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let is_tuple = matches!(fields, Fields::Unnamed(_));
+
+    let mut synced_fields: Vec<Field> = Vec::new();
+    let mut dependencies: Vec<Vec<syn::LitStr>> = Vec::new();
+    let mut derived_fields: Vec<(Member, Path)> = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        let attrs = field_attrs(field)?;
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(index.into()),
+        };
+        match attrs.kind {
+            FieldKind::Composable => {}
+            FieldKind::Skip => continue,
+            FieldKind::Derived(function) => {
+                derived_fields.push((member, function));
+                continue;
+            }
+        }
+        let synced_member = match (&member, attrs.rename) {
+            (_, Some(rename)) => Member::Named(rename),
+            (Member::Named(ident), None) => Member::Named(ident.clone()),
+            (Member::Unnamed(_), None) => Member::Unnamed(synced_fields.len().into()),
+        };
+        let path = match &synced_member {
+            Member::Named(ident) => ident.to_string(),
+            Member::Unnamed(_) => index.to_string(),
+        };
+        synced_fields.push(Field {
+            member,
+            synced_member,
+            ty: &field.ty,
+            path,
+            after: Vec::new(),
+        });
+        dependencies.push(attrs.after);
+    }
+    let mut fields = synced_fields;
+    resolve_dependencies(&mut fields, &dependencies)?;
+    let update_order = update_order(&fields)?;
+
+    let field_types: Vec<&Type> = fields.iter().map(|field| field.ty).collect();
+
+    // Take the type of the first field to derive ParentState and Parameters
+    let Some(first_field_type) = field_types.first().copied() else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "a #[composable] struct needs at least one field that isn't skipped or derived",
+        ));
+    };
+
+    // Deltas of structs are made of Options, which makes them Default
+    attrs.delta.add_derive(syn::parse_quote!(Default));
+    let summary_name = &attrs.summary.name;
+    let delta_name = &attrs.delta.name;
+
+    let summary_field_types = field_types.iter().map(|ty| {
+        quote! { <#ty as freenet_scaffold::ComposableState>::Summary }
+    });
+
+    let delta_field_types = field_types.iter().map(|ty| {
+        quote! { Option<<#ty as freenet_scaffold::ComposableState>::Delta> }
+    });
+
+    // Children are called through fully qualified paths so that types which also implement
+    // LegacyComposableState don't make the method calls ambiguous
+    let verify_impl = update_order.iter().map(|&index| {
+        let Field {
+            member, ty, path, ..
+        } = &fields[index];
+        quote! {
+            <#ty as freenet_scaffold::ComposableState>::verify(&self.#member, parent_state, parameters)
+                .map_err(|e| e.in_field(#path))?;
+        }
+    });
+
+    // Tuple structs are built with `Name { 0: .., 1: .. }` so both kinds share this code
+    let summarize_impl = fields.iter().map(|Field { member, synced_member, ty, .. }| {
+        quote! {
+            #synced_member: <#ty as freenet_scaffold::ComposableState>::summarize(&self.#member, parent_state, parameters)
+        }
+    });
+
+    let delta_impl = fields.iter().map(|Field { member, synced_member, ty, .. }| {
+        quote! {
+            #synced_member: <#ty as freenet_scaffold::ComposableState>::delta(&self.#member, parent_state, parameters, &old_state_summary.#synced_member)
+        }
+    });
+
+    let all_none_check = fields
+        .iter()
+        .map(|Field { synced_member, .. }| {
+            quote! {
+                delta.#synced_member.is_none()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Note: we're passing self_clone as the parent_state so that dependencies between fields work
+    let apply_delta_impl = update_order.iter().map(|&index| {
+        let Field {
+            member,
+            synced_member,
+            ty,
+            path,
+            ..
+        } = &fields[index];
+        quote! {
+            let self_clone = self.clone();
+            <#ty as freenet_scaffold::ComposableState>::apply_delta(&mut self.#member, &self_clone, parameters, &delta.#synced_member)
+                .map_err(|e| e.in_field(#path))?;
+        }
+    });
+
+    let compose_impl = fields.iter().map(|Field { synced_member, ty, .. }| {
+        quote! {
+            #synced_member: freenet_scaffold::util::compose_optional_deltas::<#ty>(&first.#synced_member, &second.#synced_member)?
+        }
+    });
+
+    // Derived fields are recomputed once every synced field has been updated
+    let derive_impl = derived_fields.iter().map(|(member, function)| {
+        quote! {
+            self.#member = #function(self);
+        }
+    });
+
+    let generic = !input.generics.params.is_empty();
+    let checks = if generic {
+        // The checks can't name generic parameters, the impl's where clause covers them instead
+        TokenStream::new()
+    } else {
+        type_checks(&field_types, first_field_type)
+    };
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+    let summary_where = types_where_clause(
+        &input.generics,
+        &field_types,
+        "Summary",
+        &attrs.summary.derives,
+    );
+    let delta_where =
+        types_where_clause(&input.generics, &field_types, "Delta", &attrs.delta.derives);
+    let (summary_vis, summary_derives) = (&attrs.summary.vis, &attrs.summary.derives);
+    let (delta_vis, delta_derives) = (&attrs.delta.vis, &attrs.delta.derives);
+    let impl_where = impl_where_clause(
+        input,
+        &field_types,
+        first_field_type,
+        &[&summary_where, &delta_where],
+    );
+    // Generic Summary and Delta types rely on the where clause alone, which implies the serde
+    // bounds of the associated types, rather than on bounds serde would infer for the parameters
+    let serde_bound = if generic {
+        quote! { #[serde(bound = "")] }
+    } else {
+        TokenStream::new()
+    };
+
+    let (summary_struct, delta_struct) = if is_tuple {
+        (
+            quote! { #summary_vis struct #summary_name #impl_generics (#(#summary_vis #summary_field_types,)*) #summary_where; },
+            quote! { #delta_vis struct #delta_name #impl_generics (#(#delta_vis #delta_field_types,)*) #delta_where; },
+        )
+    } else {
+        let field_names = fields.iter().map(|field| &field.synced_member);
+        let delta_field_names = field_names.clone();
+        (
+            quote! { #summary_vis struct #summary_name #impl_generics #summary_where { #(#summary_vis #field_names: #summary_field_types,)* } },
+            quote! { #delta_vis struct #delta_name #impl_generics #delta_where { #(#delta_vis #delta_field_names: #delta_field_types,)* } },
+        )
+    };
+
+    let invertible_impl = if attrs.invertible {
+        let invert_impl = fields.iter().map(|Field { member, synced_member, ty, path, .. }| {
+            quote! {
+                #synced_member: match &delta.#synced_member {
+                    Some(field_delta) => Some(
+                        <#ty as freenet_scaffold::InvertibleState>::invert_delta(&self.#member, parent_state, parameters, field_delta)
+                            .map_err(|e| e.in_field(#path))?,
+                    ),
+                    None => None,
+                }
+            }
+        });
+        let invertible_where = invertible_where_clause(&impl_where, &field_types);
+        quote! {
+            impl #impl_generics freenet_scaffold::InvertibleState for #name #ty_generics #invertible_where {
+                fn invert_delta(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters, delta: &Self::Delta) -> Result<Self::Delta, freenet_scaffold::ScaffoldError> {
+                    Ok(#delta_name {
+                        #(#invert_impl,)*
+                    })
+                }
+            }
+        }
+    } else {
+        TokenStream::new()
+    };
+
+    let versioned_impl = versioned_impl(input, &attrs, &impl_where);
+
+    let mut input = input.clone();
+    strip_field_attrs(&mut input);
+
+    let expanded = quote! {
+        #input
+
+        // Serialize, Deserialize, Clone, PartialEq and Debug, plus any derives from summary_derive(..)
+        #[derive(#(#summary_derives),*)]
+        #serde_bound
+        #summary_struct
+
+        #[derive(#(#delta_derives),*)]
+        #serde_bound
+        #delta_struct
+
+        impl #impl_generics freenet_scaffold::ComposableState for #name #ty_generics #impl_where {
+            type ParentState = #name #ty_generics;
+            type Summary = #summary_name #ty_generics;
+            type Delta = #delta_name #ty_generics;
+            type Parameters = <#first_field_type as freenet_scaffold::ComposableState>::Parameters;
+
+            fn verify(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters) -> Result<(), freenet_scaffold::ScaffoldError> {
+                #(#verify_impl)*
+                Ok(())
+            }
+
+            fn summarize(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters) -> Self::Summary {
+                #summary_name {
+                    #(#summarize_impl,)*
+                }
+            }
+
+            fn delta(&self, parent_state: &Self::ParentState, parameters: &Self::Parameters, old_state_summary: &Self::Summary) -> Option<Self::Delta> {
+                let delta = #delta_name {
+                    #(#delta_impl,)*
+                };
+
+                if #(#all_none_check)&&* {
+                    None
+                } else {
+                    Some(delta)
+                }
+            }
+
+            // parent_state disregarded because we need to use self so that dependencies between fields work, ugly
+            fn apply_delta(&mut self, _parent_state: &Self::ParentState, parameters: &Self::Parameters, delta: &Option<Self::Delta>) -> Result<(), freenet_scaffold::ScaffoldError> {
+                if let Some(delta) = delta {
+                    #(#apply_delta_impl)*
+                }
+                #(#derive_impl)*
+                Ok(())
+            }
+
+            // Field-wise, fails if the deltas of any field can't be combined
+            fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+                Some(#delta_name {
+                    #(#compose_impl,)*
+                })
+            }
+        }
+
+        #invertible_impl
+
+        #versioned_impl
+
+        #checks
+    };
+
+    Ok(expanded)
+}
+
+fn member_name(member: &Member) -> String {
+    match member {
+        Member::Named(ident) => ident.to_string(),
+        Member::Unnamed(index) => index.index.to_string(),
+    }
+}
+
+/// Resolves the field names given to `#[composable(after = "..")]` to indices.
+fn resolve_dependencies(
+    fields: &mut [Field],
+    dependencies: &[Vec<syn::LitStr>],
+) -> syn::Result<()> {
+    let names: Vec<String> = fields
+        .iter()
+        .map(|field| member_name(&field.member))
+        .collect();
+    for (field, after) in fields.iter_mut().zip(dependencies) {
+        for name in after {
+            let Some(index) = names.iter().position(|other| *other == name.value()) else {
+                return Err(syn::Error::new(
+                    name.span(),
+                    format!(
+                        "`after` refers to `{}`, which isn't a field in the summary and delta",
+                        name.value()
+                    ),
+                ));
+            };
+            field.after.push(index);
+        }
+    }
+    Ok(())
+}
+
+/// The order in which fields are verified and updated: every field comes after the fields it
+/// names in `#[composable(after = "..")]`, and otherwise fields keep their declaration order.
+fn update_order(fields: &[Field]) -> syn::Result<Vec<usize>> {
+    let mut order: Vec<usize> = Vec::with_capacity(fields.len());
+    while order.len() < fields.len() {
+        let next = (0..fields.len()).find(|index| {
+            !order.contains(index) && fields[*index].after.iter().all(|dep| order.contains(dep))
+        });
+        match next {
+            Some(index) => order.push(index),
+            None => {
+                let unordered: Vec<&Field> = fields
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| !order.contains(index))
+                    .map(|(_, field)| field)
+                    .collect();
+                let names: Vec<String> = unordered
+                    .iter()
+                    .map(|field| format!("`{}`", member_name(&field.member)))
+                    .collect();
+                return Err(syn::Error::new_spanned(
+                    unordered[0].ty,
+                    format!(
+                        "`after` dependencies form a cycle, these fields can't be ordered: {}",
+                        names.join(", ")
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(order)
+}
@@ -0,0 +1,299 @@
+use crate::util::{hash_serialized, FastHash};
+use crate::{ComposableState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// Limits on what an [`AppendLog`] retains, every limit that is set applies.
+///
+/// Entries are pruned oldest first, by timestamp and then by id, until the log is within all
+/// limits. Because the outcome only depends on which entries are present, every peer holding
+/// the same entries prunes the same ones.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct RetentionPolicy {
+    pub max_entries: Option<usize>,
+    /// Limit on the total bincode-serialized size of the retained timestamps and values.
+    pub max_bytes: Option<u64>,
+    /// Entries older than the newest entry's timestamp minus `max_age` are pruned. Measured
+    /// against the newest entry rather than the current time, which peers don't agree on.
+    ///
+    /// Anyone who can append an entry can therefore prune every other one by giving theirs a
+    /// timestamp far in the future. Bound the timestamps with
+    /// [`LogParameters::accepts_timestamp`] when using `max_age`.
+    pub max_age: Option<u64>,
+}
+
+const UNBOUNDED: RetentionPolicy = RetentionPolicy {
+    max_entries: None,
+    max_bytes: None,
+    max_age: None,
+};
+
+/// Contract parameters that fix how an [`AppendLog`] is bounded, so that no peer can pick its
+/// own limits.
+pub trait LogParameters<S> {
+    fn retention_policy(&self) -> &RetentionPolicy;
+
+    /// Whether an entry may have `timestamp`, for example because it isn't later than a clock
+    /// kept in the parent state. Checked by `verify` and before adding the entries of a delta.
+    fn accepts_timestamp(&self, _parent_state: &S, _timestamp: u64) -> bool {
+        true
+    }
+}
+
+/// An unbounded log.
+impl<S> LogParameters<S> for () {
+    fn retention_policy(&self) -> &RetentionPolicy {
+        &UNBOUNDED
+    }
+}
+
+/// A log bounded by the policy, with any timestamp accepted.
+impl<S> LogParameters<S> for RetentionPolicy {
+    fn retention_policy(&self) -> &RetentionPolicy {
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry<T> {
+    id: FastHash,
+    timestamp: u64,
+    value: T,
+}
+
+impl<T: Serialize> LogEntry<T> {
+    fn new(timestamp: u64, value: T) -> Self {
+        LogEntry {
+            id: hash_serialized(&(timestamp, &value)),
+            timestamp,
+            value,
+        }
+    }
+
+    /// The [`hash_serialized`] of the timestamp and value.
+    pub fn id(&self) -> FastHash {
+        self.id
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    fn size(&self) -> u64 {
+        bincode::serialized_size(&(self.timestamp, &self.value))
+            .expect("entry should be serializable")
+    }
+}
+
+/// A bounded append-only log, such as the messages of a chat room.
+///
+/// Entries are identified by the hash of their timestamp and value and kept in timestamp
+/// order. After every change the log is pruned according to the [`RetentionPolicy`] from its
+/// [`LogParameters`].
+///
+/// It doesn't implement [`InvertibleState`](crate::InvertibleState), since an entry can't be
+/// removed once appended, only pruned by the retention policy.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AppendLog<T, S = (), P = ()> {
+    /// Sorted by timestamp and then by id.
+    entries: Vec<LogEntry<T>>,
+    #[serde(skip)]
+    _context: PhantomData<fn() -> (S, P)>,
+}
+
+impl<T, S, P> Default for AppendLog<T, S, P> {
+    fn default() -> Self {
+        AppendLog {
+            entries: Vec::new(),
+            _context: PhantomData,
+        }
+    }
+}
+
+impl<T, S, P> AppendLog<T, S, P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The retained entries, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &LogEntry<T>> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<T: Serialize, S, P: LogParameters<S>> AppendLog<T, S, P> {
+    /// Appends `value` and prunes the log, returning the id of the new entry.
+    pub fn push(&mut self, parameters: &P, timestamp: u64, value: T) -> FastHash {
+        let id = self.insert(LogEntry::new(timestamp, value));
+        self.prune(parameters.retention_policy());
+        id
+    }
+
+    fn insert(&mut self, entry: LogEntry<T>) -> FastHash {
+        let id = entry.id;
+        if let Err(index) = self.entries.binary_search_by(|existing| {
+            (existing.timestamp, existing.id).cmp(&(entry.timestamp, id))
+        }) {
+            self.entries.insert(index, entry);
+        }
+        id
+    }
+
+    /// The number of oldest entries that have to go for the log to satisfy `policy`.
+    fn excess(&self, policy: &RetentionPolicy) -> usize {
+        let Some(newest) = self.entries.last() else {
+            return 0;
+        };
+        let oldest_allowed = policy
+            .max_age
+            .map(|max_age| newest.timestamp.saturating_sub(max_age));
+        let mut bytes = 0u64;
+        // Walk back from the newest entry and stop at the first one that can't be kept, so
+        // the result doesn't depend on which entries were pruned earlier
+        for (kept, entry) in self.entries.iter().rev().enumerate() {
+            bytes = bytes.saturating_add(entry.size());
+            let over_count = policy.max_entries.is_some_and(|max| kept + 1 > max);
+            let over_bytes = policy.max_bytes.is_some_and(|max| bytes > max);
+            let too_old = oldest_allowed.is_some_and(|oldest| entry.timestamp < oldest);
+            if over_count || over_bytes || too_old {
+                return self.entries.len() - kept;
+            }
+        }
+        0
+    }
+
+    fn prune(&mut self, policy: &RetentionPolicy) {
+        let excess = self.excess(policy);
+        self.entries.drain(..excess);
+    }
+}
+
+impl<T, S, P> ComposableState for AppendLog<T, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: LogParameters<S> + Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    /// The ids of the retained entries.
+    type Summary = BTreeSet<FastHash>;
+    /// `(timestamp, value)` of the entries missing from the summary.
+    type Delta = Vec<(u64, T)>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        for entry in &self.entries {
+            if entry.id != hash_serialized(&(entry.timestamp, &entry.value)) {
+                return Err(ScaffoldError::verification_failed(format!(
+                    "entry {:?} does not match its timestamp and value",
+                    entry.id
+                )));
+            }
+            if !parameters.accepts_timestamp(parent_state, entry.timestamp) {
+                return Err(ScaffoldError::verification_failed(format!(
+                    "entry {:?} has an unacceptable timestamp {}",
+                    entry.id, entry.timestamp
+                )));
+            }
+        }
+        if !self
+            .entries
+            .windows(2)
+            .all(|pair| (pair[0].timestamp, pair[0].id) < (pair[1].timestamp, pair[1].id))
+        {
+            return Err(ScaffoldError::verification_failed(
+                "entries are not in timestamp order",
+            ));
+        }
+        if self.excess(parameters.retention_policy()) > 0 {
+            return Err(ScaffoldError::limit_exceeded(
+                "log holds entries its retention policy should have pruned",
+            ));
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.entries.iter().map(|entry| entry.id).collect()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let delta: Vec<(u64, T)> = self
+            .entries
+            .iter()
+            .filter(|entry| !old_state_summary.contains(&entry.id))
+            .map(|entry| (entry.timestamp, entry.value.clone()))
+            .collect();
+        if delta.is_empty() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        if let Some(delta) = delta {
+            if let Some((timestamp, _)) = delta
+                .iter()
+                .find(|(timestamp, _)| !parameters.accepts_timestamp(parent_state, *timestamp))
+            {
+                return Err(ScaffoldError::invalid_delta(format!(
+                    "entry has an unacceptable timestamp {}",
+                    timestamp
+                )));
+            }
+            for (timestamp, value) in delta {
+                self.insert(LogEntry::new(*timestamp, value.clone()));
+            }
+            self.prune(parameters.retention_policy());
+        }
+        Ok(())
+    }
+
+    /// Pruning only depends on which entries are present, so pruning once after both deltas
+    /// keeps the same entries as pruning after each of them.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        let ids: BTreeSet<FastHash> = first.iter().map(hash_serialized).collect();
+        let mut composed = first.clone();
+        composed.extend(
+            second
+                .iter()
+                .filter(|entry| !ids.contains(&hash_serialized(*entry)))
+                .cloned(),
+        );
+        Some(composed)
+    }
+}
@@ -0,0 +1,326 @@
+//! Byte formats for states, summaries and deltas.
+//!
+//! Every encoded blob starts with a [`Header`]: the [`Format`] of the rest of the blob, the
+//! version of the header itself and, for states encoded with [`Codec::encode_versioned`], the
+//! version of their schema. Decoding reads the header and uses whichever format the blob was
+//! written in, so a contract that switches formats can still read bytes from peers running older
+//! builds, and tools can show any blob with [`pretty_print`].
+
+use crate::ComposableState;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+
+/// The version of the two byte [`Header`] written by [`Codec::encode`].
+pub const HEADER_VERSION: u8 = 1;
+
+/// The version of the [`Header`] that is followed by a little-endian `u32` schema version,
+/// written by [`Codec::encode_versioned`].
+pub const VERSIONED_HEADER_VERSION: u8 = 2;
+
+/// Identifies the encoding of a blob. The ids are part of the wire format and never change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    Cbor = 1,
+    Bincode = 2,
+    /// Only available with the `json` feature.
+    Json = 3,
+}
+
+impl Format {
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, CodecError> {
+        match id {
+            1 => Ok(Format::Cbor),
+            2 => Ok(Format::Bincode),
+            3 => Ok(Format::Json),
+            _ => Err(CodecError::UnknownFormat(id)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Cbor => write!(f, "CBOR"),
+            Format::Bincode => write!(f, "bincode"),
+            Format::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+/// The start of every encoded blob.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub format: Format,
+    pub version: u8,
+    /// The schema version of the encoded state, see [`crate::schema`]. Only headers of version
+    /// [`VERSIONED_HEADER_VERSION`] have one.
+    pub schema: Option<u32>,
+}
+
+impl Header {
+    /// Splits `bytes` into its header and the encoded value.
+    pub fn read(bytes: &[u8]) -> Result<(Header, &[u8]), CodecError> {
+        let [format, version, rest @ ..] = bytes else {
+            return Err(CodecError::MissingHeader);
+        };
+        let (schema, body) = match *version {
+            HEADER_VERSION => (None, rest),
+            VERSIONED_HEADER_VERSION => {
+                let [a, b, c, d, body @ ..] = rest else {
+                    return Err(CodecError::MissingHeader);
+                };
+                (Some(u32::from_le_bytes([*a, *b, *c, *d])), body)
+            }
+            _ => return Err(CodecError::UnsupportedHeaderVersion(*version)),
+        };
+        let header = Header {
+            format: Format::from_id(*format)?,
+            version: *version,
+            schema,
+        };
+        Ok((header, body))
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![self.format.id(), self.version];
+        if let Some(schema) = self.schema {
+            bytes.extend(schema.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodecError {
+    /// The blob is too short to hold a [`Header`].
+    MissingHeader,
+    UnknownFormat(u8),
+    UnsupportedHeaderVersion(u8),
+    /// A state with a schema version its type can't be migrated from.
+    UnsupportedSchema(u32),
+    /// The blob uses a format whose cargo feature isn't enabled.
+    FormatDisabled(Format),
+    Encode(String),
+    Decode(String),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::MissingHeader => write!(f, "blob is too short to hold a header"),
+            CodecError::UnknownFormat(id) => write!(f, "unknown format id {}", id),
+            CodecError::UnsupportedHeaderVersion(version) => {
+                write!(f, "unsupported header version {}", version)
+            }
+            CodecError::UnsupportedSchema(version) => {
+                write!(f, "unsupported schema version {}", version)
+            }
+            CodecError::FormatDisabled(format) => {
+                write!(f, "support for {} is not enabled", format)
+            }
+            CodecError::Encode(reason) => write!(f, "encoding failed: {}", reason),
+            CodecError::Decode(reason) => write!(f, "decoding failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// A format values can be encoded in.
+pub trait Codec {
+    const FORMAT: Format;
+
+    /// Encodes `value` without a header.
+    fn encode_body<V: Serialize + ?Sized>(value: &V) -> Result<Vec<u8>, CodecError>;
+
+    /// Decodes a value encoded by [`Codec::encode_body`].
+    fn decode_body<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, CodecError>;
+
+    /// Encodes `value` in this format, after a [`Header`].
+    fn encode<V: Serialize + ?Sized>(value: &V) -> Result<Vec<u8>, CodecError> {
+        let header = Header {
+            format: Self::FORMAT,
+            version: HEADER_VERSION,
+            schema: None,
+        };
+        let mut bytes = header.to_bytes();
+        bytes.extend(Self::encode_body(value)?);
+        Ok(bytes)
+    }
+
+    /// Encodes `value` after a [`Header`] carrying its schema version.
+    fn encode_versioned<V: Serialize + ?Sized>(
+        value: &V,
+        schema: u32,
+    ) -> Result<Vec<u8>, CodecError> {
+        let header = Header {
+            format: Self::FORMAT,
+            version: VERSIONED_HEADER_VERSION,
+            schema: Some(schema),
+        };
+        let mut bytes = header.to_bytes();
+        bytes.extend(Self::encode_body(value)?);
+        Ok(bytes)
+    }
+
+    /// Decodes a blob in any supported format, see [`decode_any`].
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, CodecError> {
+        decode_any(bytes)
+    }
+}
+
+/// CBOR, self-describing and compact, the format contracts use by default.
+pub struct Cbor;
+
+impl Codec for Cbor {
+    const FORMAT: Format = Format::Cbor;
+
+    fn encode_body<V: Serialize + ?Sized>(value: &V) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).map_err(|e| CodecError::Encode(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    fn decode_body<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, CodecError> {
+        ciborium::from_reader(bytes).map_err(|e| CodecError::Decode(e.to_string()))
+    }
+}
+
+/// bincode, the most compact, but a blob can't be read without knowing its type.
+pub struct Bincode;
+
+impl Codec for Bincode {
+    const FORMAT: Format = Format::Bincode;
+
+    fn encode_body<V: Serialize + ?Sized>(value: &V) -> Result<Vec<u8>, CodecError> {
+        bincode::serialize(value).map_err(|e| CodecError::Encode(e.to_string()))
+    }
+
+    fn decode_body<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, CodecError> {
+        bincode::deserialize(bytes).map_err(|e| CodecError::Decode(e.to_string()))
+    }
+}
+
+/// JSON, for debugging and tools. Maps whose keys aren't strings or numbers can't be encoded.
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    const FORMAT: Format = Format::Json;
+
+    fn encode_body<V: Serialize + ?Sized>(value: &V) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(value).map_err(|e| CodecError::Encode(e.to_string()))
+    }
+
+    fn decode_body<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, CodecError> {
+        serde_json::from_slice(bytes).map_err(|e| CodecError::Decode(e.to_string()))
+    }
+}
+
+/// Decodes a blob written by any [`Codec`], using the format named in its header.
+pub fn decode_any<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, CodecError> {
+    let (header, body) = Header::read(bytes)?;
+    decode_body(header.format, body)
+}
+
+/// Decodes a blob's body, the part after its [`Header`], in the given format.
+pub fn decode_body<V: DeserializeOwned>(format: Format, body: &[u8]) -> Result<V, CodecError> {
+    match format {
+        Format::Cbor => Cbor::decode_body(body),
+        Format::Bincode => Bincode::decode_body(body),
+        #[cfg(feature = "json")]
+        Format::Json => Json::decode_body(body),
+        #[cfg(not(feature = "json"))]
+        Format::Json => Err(CodecError::FormatDisabled(Format::Json)),
+    }
+}
+
+/// Describes a blob without knowing its type: the header, followed by the value for
+/// self-describing formats or a hex dump for bincode.
+pub fn pretty_print(bytes: &[u8]) -> Result<String, CodecError> {
+    let (header, body) = Header::read(bytes)?;
+    let value = match header.format {
+        Format::Cbor => {
+            let value: ciborium::Value = Cbor::decode_body(body)?;
+            format!("{:#?}", value)
+        }
+        Format::Bincode => body
+            .chunks(16)
+            .map(|line| {
+                line.iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        #[cfg(feature = "json")]
+        Format::Json => {
+            let value: serde_json::Value = Json::decode_body(body)?;
+            serde_json::to_string_pretty(&value).map_err(|e| CodecError::Encode(e.to_string()))?
+        }
+        #[cfg(not(feature = "json"))]
+        Format::Json => return Err(CodecError::FormatDisabled(Format::Json)),
+    };
+    let schema = match header.schema {
+        Some(schema) => format!(", schema version {}", schema),
+        None => String::new(),
+    };
+    Ok(format!(
+        "{} (header version {}{}, {} bytes)\n{}",
+        header.format,
+        header.version,
+        schema,
+        body.len(),
+        value
+    ))
+}
+
+/// How a contract encodes the states, summaries and deltas of `T`.
+///
+/// Every [`Codec`] encodes them as they are, while [`crate::schema::Migrating`] records their
+/// schema version and migrates them when they were written by an older version of `T`.
+pub trait StateCodec<T: ComposableState> {
+    fn encode_state(state: &T) -> Result<Vec<u8>, CodecError>;
+    fn decode_state(bytes: &[u8]) -> Result<T, CodecError>;
+    fn encode_summary(summary: &T::Summary) -> Result<Vec<u8>, CodecError>;
+    fn decode_summary(bytes: &[u8]) -> Result<T::Summary, CodecError>;
+    fn encode_delta(delta: &Option<T::Delta>) -> Result<Vec<u8>, CodecError>;
+    fn decode_delta(bytes: &[u8]) -> Result<Option<T::Delta>, CodecError>;
+}
+
+impl<T, C> StateCodec<T> for C
+where
+    T: ComposableState + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    fn encode_state(state: &T) -> Result<Vec<u8>, CodecError> {
+        C::encode(state)
+    }
+
+    fn decode_state(bytes: &[u8]) -> Result<T, CodecError> {
+        C::decode(bytes)
+    }
+
+    fn encode_summary(summary: &T::Summary) -> Result<Vec<u8>, CodecError> {
+        C::encode(summary)
+    }
+
+    fn decode_summary(bytes: &[u8]) -> Result<T::Summary, CodecError> {
+        C::decode(bytes)
+    }
+
+    fn encode_delta(delta: &Option<T::Delta>) -> Result<Vec<u8>, CodecError> {
+        C::encode(delta)
+    }
+
+    fn decode_delta(bytes: &[u8]) -> Result<Option<T::Delta>, CodecError> {
+        C::decode(bytes)
+    }
+}
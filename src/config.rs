@@ -0,0 +1,210 @@
+//! Settings that only the owner of a contract may change.
+
+use crate::signed::{signed_bytes, Signer, Verifier};
+use crate::util::{hash_serialized, FastHash};
+use crate::{ComposableState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// Contract parameters naming the owner, whose key signs an [`OwnerConfig`].
+pub trait OwnerKey<K> {
+    fn owner_key(&self) -> &K;
+}
+
+/// A key is its own owner key, for contracts whose parameters are just the owner's key.
+impl<K> OwnerKey<K> for K {
+    fn owner_key(&self) -> &K {
+        self
+    }
+}
+
+/// A configuration signed by the contract's owner, whose key is taken from the parameters.
+///
+/// Every change increments the version and is signed together with it. A configuration replaces
+/// the current one if its version is higher or, when the owner has signed two configurations
+/// with the same version, for example from two devices, if its signature has the lower
+/// [`hash_serialized`], so every peer settles on the same one. A delta that loses, such as a
+/// duplicated or replayed one, is ignored like a stale delta to a [`Signed`](crate::signed::Signed) value, while a
+/// delta not signed by the owner is rejected.
+///
+/// It doesn't implement [`InvertibleState`](crate::InvertibleState): only the owner can sign
+/// the previous configuration again as a newer version.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned"))]
+pub struct OwnerConfig<T, V: Verifier, S = (), P = ()> {
+    config: T,
+    version: u64,
+    signature: V::Signature,
+    #[serde(skip)]
+    _context: PhantomData<fn() -> (S, P)>,
+}
+
+/// A newer configuration, signed by the owner.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OwnerConfigDelta<T, Signature> {
+    pub config: T,
+    pub version: u64,
+    pub signature: Signature,
+}
+
+impl<T: Serialize, V: Verifier, S, P: Serialize> OwnerConfig<T, V, S, P> {
+    /// The initial configuration, at version 0, for the contract with `parameters`.
+    pub fn new(config: T, owner: &impl Signer<V>, parameters: &P) -> Self {
+        Self::signed(config, 0, owner, parameters)
+    }
+
+    pub fn get(&self) -> &T {
+        &self.config
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Replaces the configuration with the next version, signed by `owner`. Fails if the
+    /// version is already `u64::MAX`.
+    pub fn update(
+        &mut self,
+        config: T,
+        owner: &impl Signer<V>,
+        parameters: &P,
+    ) -> Result<(), ScaffoldError> {
+        let version = self.version.checked_add(1).ok_or_else(|| {
+            ScaffoldError::limit_exceeded("the configuration has reached the highest version")
+        })?;
+        *self = Self::signed(config, version, owner, parameters);
+        Ok(())
+    }
+
+    fn signed(config: T, version: u64, owner: &impl Signer<V>, parameters: &P) -> Self {
+        let message = signed_bytes(OWNER_CONFIG_PURPOSE, parameters, &(version, &config));
+        let signature = owner.sign(&message);
+        OwnerConfig {
+            config,
+            version,
+            signature,
+            _context: PhantomData,
+        }
+    }
+}
+
+/// Orders configurations by version and then by the hash of their signature, lowest first.
+fn precedence(version: u64, signature_hash: FastHash) -> (u64, Reverse<FastHash>) {
+    (version, Reverse(signature_hash))
+}
+
+/// Tags the signatures of [`OwnerConfig`]s, see [`signed_bytes`].
+const OWNER_CONFIG_PURPOSE: &str = "freenet-scaffold/owner-config/v1";
+
+/// Checks that the owner signed `config` at `version`.
+fn check_owner_signature<T, V, P>(
+    config: &T,
+    version: u64,
+    signature: &V::Signature,
+    parameters: &P,
+) -> Result<(), ScaffoldError>
+where
+    T: Serialize,
+    V: Verifier,
+    P: OwnerKey<V::PublicKey> + Serialize,
+{
+    let message = signed_bytes(OWNER_CONFIG_PURPOSE, parameters, &(version, config));
+    if V::verify(parameters.owner_key(), &message, signature) {
+        Ok(())
+    } else {
+        Err(ScaffoldError::unauthorized(format!(
+            "version {} is not signed by the owner",
+            version
+        )))
+    }
+}
+
+impl<T, V, S, P> ComposableState for OwnerConfig<T, V, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    V: Verifier,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: OwnerKey<V::PublicKey> + Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    /// The version of the configuration and the hash of its signature.
+    type Summary = (u64, FastHash);
+    type Delta = OwnerConfigDelta<T, V::Signature>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        check_owner_signature::<T, V, P>(&self.config, self.version, &self.signature, parameters)
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        (self.version, hash_serialized(&self.signature))
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let (old_version, old_signature) = *old_state_summary;
+        if precedence(self.version, hash_serialized(&self.signature))
+            > precedence(old_version, old_signature)
+        {
+            Some(OwnerConfigDelta {
+                config: self.config.clone(),
+                version: self.version,
+                signature: self.signature.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        let Some(delta) = delta else {
+            return Ok(());
+        };
+        check_owner_signature::<T, V, P>(
+            &delta.config,
+            delta.version,
+            &delta.signature,
+            parameters,
+        )?;
+        if precedence(delta.version, hash_serialized(&delta.signature))
+            <= precedence(self.version, hash_serialized(&self.signature))
+        {
+            return Ok(());
+        }
+        self.config = delta.config.clone();
+        self.version = delta.version;
+        self.signature = delta.signature.clone();
+        Ok(())
+    }
+
+    /// The second delta, which can only follow the first if it wins against it.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        if precedence(second.version, hash_serialized(&second.signature))
+            > precedence(first.version, hash_serialized(&first.signature))
+        {
+            Some(second.clone())
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Turns a root [`ComposableState`], one whose `ParentState` is itself, into the operations of
+//! a Freenet contract.
+//!
+//! [`ContractAdapter`] works on the raw bytes a contract receives and returns, encoding states,
+//! summaries and deltas with a [`Codec`], CBOR by default, so a contract's `ContractInterface`
+//! implementation only has to forward each call and convert between these types and the ones
+//! from `freenet-stdlib`:
+//!
+//! ```ignore
+//! impl ContractInterface for Contract {
+//!     fn summarize_state(parameters: Parameters<'static>, state: State<'static>)
+//!         -> Result<StateSummary<'static>, ContractError> {
+//!         ContractAdapter::<ChatRoom>::summarize_state(parameters.as_ref(), state.as_ref())
+//!             .map(StateSummary::from)
+//!             .map_err(into_freenet_error)
+//!     }
+//!     // ...
+//! }
+//! ```
+
+use crate::codec::{decode_any, Cbor, Codec, CodecError, Format, StateCodec};
+use crate::{ComposableState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Mirrors `freenet_stdlib::prelude::ContractError`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ContractError {
+    /// Bytes that couldn't be decoded as the expected type.
+    Deser(String),
+    InvalidDelta,
+    InvalidState,
+    InvalidUpdate,
+    InvalidUpdateWithInfo {
+        reason: String,
+    },
+    Other(String),
+}
+
+impl fmt::Display for ContractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContractError::Deser(reason) => write!(f, "deserialization failed: {}", reason),
+            ContractError::InvalidDelta => write!(f, "invalid delta"),
+            ContractError::InvalidState => write!(f, "invalid state"),
+            ContractError::InvalidUpdate => write!(f, "invalid update"),
+            ContractError::InvalidUpdateWithInfo { reason } => {
+                write!(f, "invalid update: {}", reason)
+            }
+            ContractError::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ContractError {}
+
+/// A rejected update, with the [`ScaffoldError`] as its reason.
+impl From<ScaffoldError> for ContractError {
+    fn from(error: ScaffoldError) -> Self {
+        ContractError::InvalidUpdateWithInfo {
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl From<CodecError> for ContractError {
+    fn from(error: CodecError) -> Self {
+        match error {
+            CodecError::Encode(_) => ContractError::Other(error.to_string()),
+            _ => ContractError::Deser(error.to_string()),
+        }
+    }
+}
+
+/// Mirrors `freenet_stdlib::prelude::ValidateResult`, without requests for related contracts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidateResult {
+    Valid,
+    Invalid,
+}
+
+/// Mirrors `freenet_stdlib::prelude::UpdateData`, without related contract states.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UpdateData<'a> {
+    /// A full state from another peer, merged into the current one.
+    State(&'a [u8]),
+    Delta(&'a [u8]),
+    StateAndDelta {
+        state: &'a [u8],
+        delta: &'a [u8],
+    },
+}
+
+/// The contract operations for the root state `T`.
+///
+/// States, summaries and deltas are encoded with `C`: a [`Codec`], or
+/// [`Migrating`](crate::schema::Migrating) for a versioned state. Deltas are encoded as
+/// `Option<T::Delta>`.
+///
+/// Parameters are decoded as `T::Parameters` from the format named in their [`Header`](crate::codec::Header), or as
+/// plain CBOR if they don't start with one, since they are usually written by whoever deploys
+/// the contract rather than by a [`Codec`]. Empty parameter bytes are read as `()`, so that
+/// contracts without parameters can use `()`.
+pub struct ContractAdapter<T, C = Cbor>(PhantomData<fn() -> (T, C)>);
+
+impl<T, C> ContractAdapter<T, C>
+where
+    T: ComposableState<ParentState = T> + Clone + Serialize + DeserializeOwned,
+    C: StateCodec<T>,
+{
+    pub fn validate_state(
+        parameters: &[u8],
+        state: &[u8],
+    ) -> Result<ValidateResult, ContractError> {
+        let parameters = decode_parameters::<T>(parameters)?;
+        let state: T = C::decode_state(state)?;
+        Ok(match state.verify(&state, &parameters) {
+            Ok(()) => ValidateResult::Valid,
+            Err(_) => ValidateResult::Invalid,
+        })
+    }
+
+    /// Applies every update in turn and returns the new state, which must pass `verify`.
+    pub fn update_state(
+        parameters: &[u8],
+        state: &[u8],
+        updates: &[UpdateData],
+    ) -> Result<Vec<u8>, ContractError> {
+        let parameters = decode_parameters::<T>(parameters)?;
+        let mut state = C::decode_state(state)?;
+        for update in updates {
+            let (other_state, delta) = match update {
+                UpdateData::State(other_state) => (Some(*other_state), None),
+                UpdateData::Delta(delta) => (None, Some(*delta)),
+                UpdateData::StateAndDelta { state, delta } => (Some(*state), Some(*delta)),
+            };
+            if let Some(other_state) = other_state {
+                let other_state = C::decode_state(other_state)?;
+                other_state.verify(&other_state, &parameters)?;
+                let parent_state = state.clone();
+                state.merge(&parent_state, &parameters, &other_state)?;
+            }
+            if let Some(delta) = delta {
+                let delta = C::decode_delta(delta)?;
+                let parent_state = state.clone();
+                state.apply_delta(&parent_state, &parameters, &delta)?;
+            }
+        }
+        state.verify(&state, &parameters)?;
+        Ok(C::encode_state(&state)?)
+    }
+
+    pub fn summarize_state(parameters: &[u8], state: &[u8]) -> Result<Vec<u8>, ContractError> {
+        let parameters = decode_parameters::<T>(parameters)?;
+        let state: T = C::decode_state(state)?;
+        Ok(C::encode_summary(&state.summarize(&state, &parameters))?)
+    }
+
+    /// The delta from the summarized state to `state`, encoded as `Option<T::Delta>`.
+    pub fn get_state_delta(
+        parameters: &[u8],
+        state: &[u8],
+        summary: &[u8],
+    ) -> Result<Vec<u8>, ContractError> {
+        let parameters = decode_parameters::<T>(parameters)?;
+        let state: T = C::decode_state(state)?;
+        let summary = C::decode_summary(summary)?;
+        Ok(C::encode_delta(&state.delta(
+            &state,
+            &parameters,
+            &summary,
+        ))?)
+    }
+}
+
+fn decode_parameters<T: ComposableState>(bytes: &[u8]) -> Result<T::Parameters, ContractError> {
+    if bytes.is_empty() {
+        return Ok(Cbor::decode_body(&Cbor::encode_body(&())?)?);
+    }
+    // A CBOR value that starts with a format id is a small integer, a single byte long, so
+    // anything longer starting with one has a header
+    if bytes.len() > 1 && Format::from_id(bytes[0]).is_ok() {
+        Ok(decode_any(bytes)?)
+    } else {
+        Ok(Cbor::decode_body(bytes)?)
+    }
+}
@@ -0,0 +1,324 @@
+use crate::util::compose_optional_deltas;
+use crate::{ComposableState, InvertibleState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// A grow-only counter keyed by peer or author id.
+///
+/// Each key only ever increments its own entry, the value of the counter is the sum of all
+/// entries, and merging takes the per-key maximum, so concurrent merges are idempotent and
+/// order-independent. `S` and `P` are the `ParentState` and `Parameters` of the enclosing
+/// `#[composable]` struct.
+///
+/// It doesn't implement [`InvertibleState`], since an increment can't be taken back. Use a
+/// [`PnCounter`] for counts that can be undone.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GCounter<K: Ord, S = (), P = ()> {
+    counts: BTreeMap<K, u64>,
+    #[serde(skip)]
+    _context: PhantomData<fn() -> (S, P)>,
+}
+
+impl<K: Ord, S, P> Default for GCounter<K, S, P> {
+    fn default() -> Self {
+        GCounter {
+            counts: BTreeMap::new(),
+            _context: PhantomData,
+        }
+    }
+}
+
+impl<K: Ord, S, P> GCounter<K, S, P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `n` to the entry for `key`, saturating at `u64::MAX`.
+    pub fn increment(&mut self, key: K, n: u64) {
+        let count = self.counts.entry(key).or_insert(0);
+        *count = count.saturating_add(n);
+    }
+
+    /// The sum of all entries, saturating at `u64::MAX`.
+    pub fn value(&self) -> u64 {
+        self.counts
+            .values()
+            .fold(0u64, |total, count| total.saturating_add(*count))
+    }
+
+    /// The entry for `key`, or zero if it has never been incremented.
+    pub fn get(&self, key: &K) -> u64 {
+        self.counts.get(key).copied().unwrap_or(0)
+    }
+
+    pub fn counts(&self) -> &BTreeMap<K, u64> {
+        &self.counts
+    }
+}
+
+impl<K, S, P> ComposableState for GCounter<K, S, P>
+where
+    K: Ord + Clone + Debug + Serialize + DeserializeOwned,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    /// The count held for each key.
+    type Summary = BTreeMap<K, u64>;
+    /// The entries that are higher than in the summary the delta was computed against.
+    type Delta = BTreeMap<K, u64>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.counts.clone()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let delta: BTreeMap<K, u64> = self
+            .counts
+            .iter()
+            .filter(|(key, count)| old_state_summary.get(key).is_none_or(|old| *count > old))
+            .map(|(key, count)| (key.clone(), *count))
+            .collect();
+        if delta.is_empty() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        if let Some(delta) = delta {
+            for (key, count) in delta {
+                let current = self.counts.entry(key.clone()).or_insert(0);
+                *current = (*current).max(*count);
+            }
+        }
+        Ok(())
+    }
+
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        let mut composed = first.clone();
+        for (key, count) in second {
+            let current = composed.entry(key.clone()).or_insert(0);
+            *current = (*current).max(*count);
+        }
+        Some(composed)
+    }
+}
+
+/// A counter that supports both increments and decrements.
+///
+/// Implemented as a pair of [`GCounter`]s, one for increments and one for decrements, so it
+/// converges regardless of the order in which deltas arrive.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PnCounter<K: Ord, S = (), P = ()> {
+    increments: GCounter<K, S, P>,
+    decrements: GCounter<K, S, P>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PnCounterSummary<K: Ord> {
+    pub increments: BTreeMap<K, u64>,
+    pub decrements: BTreeMap<K, u64>,
+}
+
+/// Only the entries that grew; either side is `None` if none of its entries did.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PnCounterDelta<K: Ord> {
+    pub increments: Option<BTreeMap<K, u64>>,
+    pub decrements: Option<BTreeMap<K, u64>>,
+}
+
+impl<K: Ord, S, P> Default for PnCounter<K, S, P> {
+    fn default() -> Self {
+        PnCounter {
+            increments: GCounter::default(),
+            decrements: GCounter::default(),
+        }
+    }
+}
+
+impl<K: Ord, S, P> PnCounter<K, S, P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&mut self, actor: K, n: u64) {
+        self.increments.increment(actor, n);
+    }
+
+    pub fn decrement(&mut self, actor: K, n: u64) {
+        self.decrements.increment(actor, n);
+    }
+
+    /// Total increments minus total decrements.
+    pub fn value(&self) -> i128 {
+        i128::from(self.increments.value()) - i128::from(self.decrements.value())
+    }
+}
+
+impl<K, S, P> ComposableState for PnCounter<K, S, P>
+where
+    K: Ord + Clone + Debug + Serialize + DeserializeOwned,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    type Summary = PnCounterSummary<K>;
+    type Delta = PnCounterDelta<K>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        self.increments
+            .verify(parent_state, parameters)
+            .map_err(|e| e.in_field("increments"))?;
+        self.decrements
+            .verify(parent_state, parameters)
+            .map_err(|e| e.in_field("decrements"))
+    }
+
+    fn summarize(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        PnCounterSummary {
+            increments: self.increments.summarize(parent_state, parameters),
+            decrements: self.decrements.summarize(parent_state, parameters),
+        }
+    }
+
+    fn delta(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let increments =
+            self.increments
+                .delta(parent_state, parameters, &old_state_summary.increments);
+        let decrements =
+            self.decrements
+                .delta(parent_state, parameters, &old_state_summary.decrements);
+        if increments.is_none() && decrements.is_none() {
+            None
+        } else {
+            Some(PnCounterDelta {
+                increments,
+                decrements,
+            })
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        if let Some(delta) = delta {
+            self.increments
+                .apply_delta(parent_state, parameters, &delta.increments)
+                .map_err(|e| e.in_field("increments"))?;
+            self.decrements
+                .apply_delta(parent_state, parameters, &delta.decrements)
+                .map_err(|e| e.in_field("decrements"))?;
+        }
+        Ok(())
+    }
+
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        Some(PnCounterDelta {
+            increments: compose_optional_deltas::<GCounter<K, S, P>>(
+                &first.increments,
+                &second.increments,
+            )?,
+            decrements: compose_optional_deltas::<GCounter<K, S, P>>(
+                &first.decrements,
+                &second.decrements,
+            )?,
+        })
+    }
+}
+
+impl<K, S, P> InvertibleState for PnCounter<K, S, P>
+where
+    K: Ord + Clone + Debug + Serialize + DeserializeOwned,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    /// Decrements by what the delta incremented and increments by what it decremented, under
+    /// the same keys.
+    fn invert_delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Self::Delta,
+    ) -> Result<Self::Delta, ScaffoldError> {
+        // Each side of the inverse has to grow past the counts the delta leaves behind
+        let invert = |counter: &GCounter<K, S, P>,
+                      grown: &Option<BTreeMap<K, u64>>,
+                      opposite: &GCounter<K, S, P>,
+                      opposite_delta: &Option<BTreeMap<K, u64>>| {
+            let inverse: BTreeMap<K, u64> = grown
+                .iter()
+                .flatten()
+                .filter(|(key, count)| **count > counter.get(key))
+                .map(|(key, count)| {
+                    let opposite_after = opposite_delta
+                        .as_ref()
+                        .and_then(|delta| delta.get(key).copied())
+                        .unwrap_or(0)
+                        .max(opposite.get(key));
+                    let growth = count - counter.get(key);
+                    (key.clone(), opposite_after.saturating_add(growth))
+                })
+                .collect();
+            (!inverse.is_empty()).then_some(inverse)
+        };
+        Ok(PnCounterDelta {
+            increments: invert(
+                &self.decrements,
+                &delta.decrements,
+                &self.increments,
+                &delta.increments,
+            ),
+            decrements: invert(
+                &self.increments,
+                &delta.increments,
+                &self.decrements,
+                &delta.decrements,
+            ),
+        })
+    }
+}
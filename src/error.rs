@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Error returned by the fallible [`ComposableState`](crate::ComposableState) methods.
+///
+/// Every variant carries the path of the field that failed, outermost field first. Leaf
+/// implementations create errors with an empty path, and `#[composable]` prepends the name of
+/// each field as the error passes up through its parent.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ScaffoldError {
+    /// A delta was malformed or could not be applied to the current state.
+    InvalidDelta { path: Vec<String>, reason: String },
+    /// The state failed its own consistency checks.
+    VerificationFailed { path: Vec<String>, reason: String },
+    /// A change was not made by someone permitted to make it, or a signature did not verify.
+    Unauthorized { path: Vec<String>, reason: String },
+    /// A size, count or rate limit was exceeded.
+    LimitExceeded { path: Vec<String>, reason: String },
+    /// Any other error, including errors converted from `String`.
+    Custom { path: Vec<String>, reason: String },
+}
+
+impl ScaffoldError {
+    pub fn invalid_delta(reason: impl Into<String>) -> Self {
+        ScaffoldError::InvalidDelta {
+            path: Vec::new(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn verification_failed(reason: impl Into<String>) -> Self {
+        ScaffoldError::VerificationFailed {
+            path: Vec::new(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn unauthorized(reason: impl Into<String>) -> Self {
+        ScaffoldError::Unauthorized {
+            path: Vec::new(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn limit_exceeded(reason: impl Into<String>) -> Self {
+        ScaffoldError::LimitExceeded {
+            path: Vec::new(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn custom(reason: impl Into<String>) -> Self {
+        ScaffoldError::Custom {
+            path: Vec::new(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Prepends `field` to the error's path, used when the error passes up through a parent.
+    pub fn in_field(mut self, field: impl Into<String>) -> Self {
+        self.path_mut().insert(0, field.into());
+        self
+    }
+
+    /// The path of the failing field, outermost field first.
+    pub fn path(&self) -> &[String] {
+        match self {
+            ScaffoldError::InvalidDelta { path, .. }
+            | ScaffoldError::VerificationFailed { path, .. }
+            | ScaffoldError::Unauthorized { path, .. }
+            | ScaffoldError::LimitExceeded { path, .. }
+            | ScaffoldError::Custom { path, .. } => path,
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        match self {
+            ScaffoldError::InvalidDelta { reason, .. }
+            | ScaffoldError::VerificationFailed { reason, .. }
+            | ScaffoldError::Unauthorized { reason, .. }
+            | ScaffoldError::LimitExceeded { reason, .. }
+            | ScaffoldError::Custom { reason, .. } => reason,
+        }
+    }
+
+    fn path_mut(&mut self) -> &mut Vec<String> {
+        match self {
+            ScaffoldError::InvalidDelta { path, .. }
+            | ScaffoldError::VerificationFailed { path, .. }
+            | ScaffoldError::Unauthorized { path, .. }
+            | ScaffoldError::LimitExceeded { path, .. }
+            | ScaffoldError::Custom { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for ScaffoldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            ScaffoldError::InvalidDelta { .. } => "invalid delta",
+            ScaffoldError::VerificationFailed { .. } => "verification failed",
+            ScaffoldError::Unauthorized { .. } => "unauthorized",
+            ScaffoldError::LimitExceeded { .. } => "limit exceeded",
+            ScaffoldError::Custom { .. } => "error",
+        };
+        if self.path().is_empty() {
+            write!(f, "{}: {}", kind, self.reason())
+        } else {
+            write!(
+                f,
+                "{} at `{}`: {}",
+                kind,
+                self.path().join("."),
+                self.reason()
+            )
+        }
+    }
+}
+
+impl std::error::Error for ScaffoldError {}
+
+impl From<String> for ScaffoldError {
+    fn from(reason: String) -> Self {
+        ScaffoldError::custom(reason)
+    }
+}
+
+impl From<&str> for ScaffoldError {
+    fn from(reason: &str) -> Self {
+        ScaffoldError::custom(reason)
+    }
+}
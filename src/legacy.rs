@@ -0,0 +1,107 @@
+//! Migration path for implementations written against the original `Result<(), String>`
+//! signatures.
+//!
+//! Changing `impl ComposableState for T` to `impl LegacyComposableState for T` is all an
+//! existing implementation needs to keep compiling. It then implements [`ComposableState`]
+//! through a blanket impl, with every `String` error reported as [`ScaffoldError::Custom`], so
+//! it can still be used as a field of a `#[composable]` struct.
+
+use crate::{ComposableState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// [`ComposableState`] as it was before [`ScaffoldError`] was introduced.
+pub trait LegacyComposableState {
+    type ParentState: Serialize + DeserializeOwned + Clone + Debug;
+    type Summary: Serialize + DeserializeOwned + Clone + Debug;
+    type Delta: Serialize + DeserializeOwned + Clone + Debug;
+    type Parameters: Serialize + DeserializeOwned + Clone + Debug;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), String>;
+    fn summarize(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Self::Summary;
+    fn delta(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta>;
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), String>;
+
+    fn merge(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        other_state: &Self,
+    ) -> Result<(), String> {
+        let my_summary = self.summarize(parent_state, parameters);
+        let delta_in = other_state.delta(parent_state, parameters, &my_summary);
+        self.apply_delta(parent_state, parameters, &delta_in)?;
+        Ok(())
+    }
+}
+
+impl<T: LegacyComposableState> ComposableState for T {
+    type ParentState = T::ParentState;
+    type Summary = T::Summary;
+    type Delta = T::Delta;
+    type Parameters = T::Parameters;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        LegacyComposableState::verify(self, parent_state, parameters).map_err(ScaffoldError::from)
+    }
+
+    fn summarize(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        LegacyComposableState::summarize(self, parent_state, parameters)
+    }
+
+    fn delta(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        LegacyComposableState::delta(self, parent_state, parameters, old_state_summary)
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        LegacyComposableState::apply_delta(self, parent_state, parameters, delta)
+            .map_err(ScaffoldError::from)
+    }
+
+    fn merge(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        other_state: &Self,
+    ) -> Result<(), ScaffoldError> {
+        LegacyComposableState::merge(self, parent_state, parameters, other_state)
+            .map_err(ScaffoldError::from)
+    }
+}
@@ -1,6 +1,26 @@
+pub mod append_log;
+pub mod codec;
+pub mod config;
+pub mod contract;
+pub mod counter;
+pub mod error;
+pub mod legacy;
+pub mod map;
+pub mod register;
+pub mod schema;
+pub mod sequence;
+pub mod set;
+pub mod signed;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod util;
+pub mod version_vector;
 
+pub use error::ScaffoldError;
 pub use freenet_scaffold_macro::*;
+pub use legacy::LegacyComposableState;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::fmt::Debug;
@@ -15,7 +35,7 @@ pub trait ComposableState {
         &self,
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
-    ) -> Result<(), String>;
+    ) -> Result<(), ScaffoldError>;
     fn summarize(
         &self,
         parent_state: &Self::ParentState,
@@ -37,7 +57,7 @@ pub trait ComposableState {
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String>;
+    ) -> Result<(), ScaffoldError>;
 
     /// Merges the current state with another state.
     fn merge(
@@ -45,13 +65,73 @@ pub trait ComposableState {
         parent_state: &Self::ParentState,
         parameters: &Self::Parameters,
         other_state: &Self,
-    ) -> Result<(), String> {
+    ) -> Result<(), ScaffoldError> {
         let my_summary = self.summarize(parent_state, parameters);
         let delta_in = other_state.delta(parent_state, parameters, &my_summary);
         self.apply_delta(parent_state, parameters, &delta_in)?;
         Ok(())
     }
+
+    /// Combines two deltas into one that has the same effect as applying `first` and then
+    /// `second`, without needing the state they apply to.
+    ///
+    /// Returns `None` if the deltas can't be combined, in which case both have to be applied in
+    /// turn. That is what the default implementation does.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta>
+    where
+        Self: Sized,
+    {
+        let _ = (first, second);
+        None
+    }
+}
+
+/// A [`ComposableState`] whose deltas can be undone.
+///
+/// Most states only ever grow, so undoing a delta doesn't restore the old state exactly: it
+/// produces a new delta whose effect cancels the original, such as a decrement for an increment
+/// or a removal for an addition. Applied on top of the original delta, the inverse brings back
+/// what the user sees, and like any other delta it can be sent to other peers.
+pub trait InvertibleState: ComposableState {
+    /// The inverse of `delta`, where `self` is the state `delta` was applied to.
+    ///
+    /// Fails if the delta can't be undone, for example because it adds an entry to a map that
+    /// never removes entries.
+    fn invert_delta(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Self::Delta,
+    ) -> Result<Self::Delta, ScaffoldError>;
 }
 
+/// `#[composable]` rejects a generated type less visible than the type it belongs to:
+///
+/// ```compile_fail
+/// use freenet_scaffold::composable;
+/// use freenet_scaffold::set::GSet;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[composable(delta_vis = "pub(crate)")]
+/// #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// pub struct Guestbook {
+///     names: GSet<String, Guestbook>,
+/// }
+/// ```
+///
+/// ```
+/// use freenet_scaffold::composable;
+/// use freenet_scaffold::set::GSet;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[composable(delta_vis = "pub(crate)")]
+/// #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// pub(crate) struct Guestbook {
+///     names: GSet<String, Guestbook>,
+/// }
+/// ```
+#[cfg(doctest)]
+pub struct ComposableVisibility;
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,269 @@
+use crate::sequence::OpId;
+use crate::util::{FastHash, VersionedHash};
+use crate::{ComposableState, InvertibleState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
+
+/// A key of a [`ComposableMap`], formatted as a segment of the path of an entry's errors.
+///
+/// Implemented for every `Display` type and for the crate's ids, which have no `Display`.
+pub trait MapKey {
+    fn path_segment(&self) -> String;
+}
+
+impl<K: Display + ?Sized> MapKey for K {
+    fn path_segment(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl MapKey for FastHash {
+    fn path_segment(&self) -> String {
+        format!("{:016x}", self.0 as u64)
+    }
+}
+
+impl MapKey for VersionedHash {
+    fn path_segment(&self) -> String {
+        self.hash.path_segment()
+    }
+}
+
+impl<A: MapKey> MapKey for OpId<A> {
+    fn path_segment(&self) -> String {
+        format!("{}@{}", self.lamport, self.actor.path_segment())
+    }
+}
+
+/// An ordered map whose values are themselves composable.
+///
+/// Entries are never removed. New entries are sent whole, while entries the other side already
+/// has are synchronized through their own summaries and deltas. The parent state and parameters
+/// are passed through to every value unchanged, so `V::ParentState` is usually the struct that
+/// holds the map, as it would be for a field of a `#[composable]` struct. Errors from an entry
+/// have its key, formatted by [`MapKey`], in their path.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ComposableMap<K: Ord, V> {
+    entries: BTreeMap<K, V>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ComposableMapDelta<K: Ord, V: ComposableState> {
+    /// Entries missing from the summary, sent whole.
+    pub added: BTreeMap<K, V>,
+    /// Deltas for entries present in the summary that have changed since.
+    pub updated: BTreeMap<K, V::Delta>,
+}
+
+impl<K: Ord, V> Default for ComposableMap<K, V> {
+    fn default() -> Self {
+        ComposableMap {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, V> ComposableMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an entry, returning `false` and leaving the map unchanged if `key` is present.
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        if self.entries.contains_key(&key) {
+            return false;
+        }
+        self.entries.insert(key, value);
+        true
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    /// Mutable access for local changes, which are picked up by the next delta.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.entries.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K, V> ComposableState for ComposableMap<K, V>
+where
+    K: Ord + Serialize + DeserializeOwned + Clone + Debug + MapKey,
+    V: ComposableState + Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = V::ParentState;
+    type Summary = BTreeMap<K, V::Summary>;
+    type Delta = ComposableMapDelta<K, V>;
+    type Parameters = V::Parameters;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        for (key, value) in &self.entries {
+            value
+                .verify(parent_state, parameters)
+                .map_err(|e| e.in_field(key.path_segment()))?;
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.clone(), value.summarize(parent_state, parameters)))
+            .collect()
+    }
+
+    fn delta(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let mut added = BTreeMap::new();
+        let mut updated = BTreeMap::new();
+        for (key, value) in &self.entries {
+            match old_state_summary.get(key) {
+                Some(old_summary) => {
+                    if let Some(delta) = value.delta(parent_state, parameters, old_summary) {
+                        updated.insert(key.clone(), delta);
+                    }
+                }
+                None => {
+                    added.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        if added.is_empty() && updated.is_empty() {
+            None
+        } else {
+            Some(ComposableMapDelta { added, updated })
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        let Some(delta) = delta else {
+            return Ok(());
+        };
+        if let Some(key) = delta
+            .updated
+            .keys()
+            .find(|key| !self.entries.contains_key(key))
+        {
+            return Err(
+                ScaffoldError::invalid_delta("update for an entry that doesn't exist")
+                    .in_field(key.path_segment()),
+            );
+        }
+        // Like the fields of a #[composable] struct, entries without a delta still get a
+        // chance to react to changes elsewhere in the parent state
+        for (key, value) in self.entries.iter_mut() {
+            value
+                .apply_delta(parent_state, parameters, &delta.updated.get(key).cloned())
+                .map_err(|e| e.in_field(key.path_segment()))?;
+        }
+        for (key, value) in &delta.added {
+            match self.entries.get_mut(key) {
+                // Added concurrently on both sides
+                Some(existing) => existing
+                    .merge(parent_state, parameters, value)
+                    .map_err(|e| e.in_field(key.path_segment()))?,
+                None => {
+                    self.entries.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if an entry added by `first` is added or updated again by `second`, which would
+    /// need the entry's state to combine.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        let mut composed = first.clone();
+        for (key, value) in &second.added {
+            if first.added.contains_key(key) {
+                return None;
+            }
+            // Updates are applied before additions, so an update from `first` still goes first
+            composed.added.insert(key.clone(), value.clone());
+        }
+        for (key, delta) in &second.updated {
+            if first.added.contains_key(key) {
+                return None;
+            }
+            let delta = match first.updated.get(key) {
+                Some(first) => V::compose_deltas(first, delta)?,
+                None => delta.clone(),
+            };
+            composed.updated.insert(key.clone(), delta);
+        }
+        Some(composed)
+    }
+}
+
+impl<K, V> InvertibleState for ComposableMap<K, V>
+where
+    K: Ord + Serialize + DeserializeOwned + Clone + Debug + MapKey,
+    V: InvertibleState + Serialize + DeserializeOwned + Clone + Debug,
+{
+    /// Inverts the update of every entry. Entries are never removed, so a delta that adds
+    /// entries can't be inverted.
+    fn invert_delta(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Self::Delta,
+    ) -> Result<Self::Delta, ScaffoldError> {
+        if let Some(key) = delta.added.keys().next() {
+            return Err(
+                ScaffoldError::invalid_delta("an added entry can't be removed")
+                    .in_field(key.path_segment()),
+            );
+        }
+        let mut updated = BTreeMap::new();
+        for (key, entry_delta) in &delta.updated {
+            let entry = self.entries.get(key).ok_or_else(|| {
+                ScaffoldError::invalid_delta("update for an entry that doesn't exist")
+                    .in_field(key.path_segment())
+            })?;
+            let inverse = entry
+                .invert_delta(parent_state, parameters, entry_delta)
+                .map_err(|e| e.in_field(key.path_segment()))?;
+            updated.insert(key.clone(), inverse);
+        }
+        Ok(ComposableMapDelta {
+            added: BTreeMap::new(),
+            updated,
+        })
+    }
+}
@@ -0,0 +1,369 @@
+use crate::signed::WholeValue;
+use crate::util::{hash_serialized, FastHash};
+use crate::version_vector::VersionVector;
+use crate::{ComposableState, InvertibleState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// A last-writer-wins register.
+///
+/// Writes are ordered by their timestamp `Ts`, which can be any ordered type such as a Unix
+/// time, a Lamport clock or a `(time, author)` pair. Writes with equal timestamps are ordered by
+/// the [`hash_serialized`] of their value, so every peer picks the same winner and concurrent
+/// writers can't flip-flop.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LwwRegister<T, Ts = u64, S = (), P = ()> {
+    value: T,
+    timestamp: Ts,
+    #[serde(skip)]
+    _context: PhantomData<fn() -> (S, P)>,
+}
+
+/// A timestamp of an [`LwwRegister`] that can be advanced, so that undoing a write can make a
+/// later one.
+pub trait Timestamp: Ord + Clone {
+    /// A timestamp later than `self`, `None` if there is none.
+    fn next(&self) -> Option<Self>;
+}
+
+macro_rules! integer_timestamp {
+    ($($ty:ty),*) => {
+        $(
+            impl Timestamp for $ty {
+                fn next(&self) -> Option<Self> {
+                    self.checked_add(1)
+                }
+            }
+        )*
+    };
+}
+
+integer_timestamp!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+/// A `(time, author)` pair advances its time and keeps the author.
+impl<Ts: Timestamp, A: Ord + Clone> Timestamp for (Ts, A) {
+    fn next(&self) -> Option<Self> {
+        Some((self.0.next()?, self.1.clone()))
+    }
+}
+
+/// A value that won against the summary it was computed for.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LwwRegisterDelta<T, Ts> {
+    pub value: T,
+    pub timestamp: Ts,
+}
+
+impl<T: Serialize, Ts: Ord + Clone, S, P> LwwRegister<T, Ts, S, P> {
+    pub fn new(value: T, timestamp: Ts) -> Self {
+        LwwRegister {
+            value,
+            timestamp,
+            _context: PhantomData,
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    pub fn timestamp(&self) -> &Ts {
+        &self.timestamp
+    }
+
+    /// Writes `value` if it wins against the current value, returning whether it did.
+    pub fn set(&mut self, value: T, timestamp: Ts) -> bool {
+        let wins = (&timestamp, hash_serialized(&value)) > (&self.timestamp, self.tie_breaker());
+        if wins {
+            self.value = value;
+            self.timestamp = timestamp;
+        }
+        wins
+    }
+
+    fn tie_breaker(&self) -> FastHash {
+        hash_serialized(&self.value)
+    }
+}
+
+impl<T, Ts, S, P> ComposableState for LwwRegister<T, Ts, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    Ts: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    /// The timestamp and tie-breaker of the current value.
+    type Summary = (Ts, FastHash);
+    type Delta = LwwRegisterDelta<T, Ts>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        (self.timestamp.clone(), self.tie_breaker())
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let (old_timestamp, old_tie_breaker) = old_state_summary;
+        if (&self.timestamp, self.tie_breaker()) > (old_timestamp, *old_tie_breaker) {
+            Some(LwwRegisterDelta {
+                value: self.value.clone(),
+                timestamp: self.timestamp.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        // A delta that lost to a write made since it was computed is ignored rather than
+        // rejected, so duplicated and reordered deltas are harmless
+        if let Some(delta) = delta {
+            self.set(delta.value.clone(), delta.timestamp.clone());
+        }
+        Ok(())
+    }
+
+    /// Whichever of the two values wins.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        let wins = (&second.timestamp, hash_serialized(&second.value))
+            > (&first.timestamp, hash_serialized(&first.value));
+        Some(if wins { second } else { first }.clone())
+    }
+}
+
+/// A delta either wins and replaces the value and timestamp, or loses and changes nothing.
+impl<T, Ts, S, P> WholeValue for LwwRegister<T, Ts, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    Ts: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+}
+
+impl<T, Ts, S, P> InvertibleState for LwwRegister<T, Ts, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    Ts: Timestamp + Serialize + DeserializeOwned + Debug,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    /// Writes the value the delta replaced again, with a timestamp after both. Fails if there
+    /// is no later timestamp.
+    fn invert_delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Self::Delta,
+    ) -> Result<Self::Delta, ScaffoldError> {
+        let latest = (&self.timestamp).max(&delta.timestamp);
+        let timestamp = latest.next().ok_or_else(|| {
+            ScaffoldError::limit_exceeded(format!("no timestamp is later than {:?}", latest))
+        })?;
+        Ok(LwwRegisterDelta {
+            value: self.value.clone(),
+            timestamp,
+        })
+    }
+}
+
+/// A multi-value register that keeps every concurrently written value.
+///
+/// Each value is tagged with the [`VersionVector`] of the write that produced it. A write
+/// replaces every value it has seen, so the register holds more than one value only while
+/// writes are concurrent, and the application can show the conflict to the user until someone
+/// resolves it with a new write.
+///
+/// It doesn't implement [`InvertibleState`]: undoing a write is a new write, whose version
+/// vector has to count it for the actor making it, and a delta doesn't say who that is.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MvRegister<T, K: Ord, S = (), P = ()> {
+    /// Sorted by version vector, no entry dominates another.
+    values: Vec<(VersionVector<K>, T)>,
+    #[serde(skip)]
+    _context: PhantomData<fn() -> (S, P)>,
+}
+
+impl<T, K: Ord, S, P> Default for MvRegister<T, K, S, P> {
+    fn default() -> Self {
+        MvRegister {
+            values: Vec::new(),
+            _context: PhantomData,
+        }
+    }
+}
+
+impl<T, K: Ord + Clone, S, P> MvRegister<T, K, S, P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces every value currently held with `value`, written by `actor`. Fails if the
+    /// actor's counter can't be incremented any further.
+    pub fn set(&mut self, actor: K, value: T) -> Result<(), ScaffoldError> {
+        let mut version = self.version_vector();
+        version.increment(actor)?;
+        self.values = vec![(version, value)];
+        Ok(())
+    }
+
+    /// The current values, more than one if concurrent writes are in conflict.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.values.iter().map(|(_, value)| value)
+    }
+
+    pub fn is_conflicted(&self) -> bool {
+        self.values.len() > 1
+    }
+
+    /// The join of the version vectors of all current values.
+    pub fn version_vector(&self) -> VersionVector<K> {
+        let mut version = VersionVector::new();
+        for (value_version, _) in &self.values {
+            version.join(value_version);
+        }
+        version
+    }
+
+    /// Adds a value unless a value already held has seen it, pruning the values it has seen.
+    fn insert(&mut self, version: VersionVector<K>, value: T) {
+        if self
+            .values
+            .iter()
+            .any(|(existing, _)| existing.dominates(&version))
+        {
+            return;
+        }
+        self.values
+            .retain(|(existing, _)| !version.dominates(existing));
+        let index = self
+            .values
+            .partition_point(|(existing, _)| *existing < version);
+        self.values.insert(index, (version, value));
+    }
+}
+
+impl<T, K, S, P> ComposableState for MvRegister<T, K, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    K: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    type Summary = VersionVector<K>;
+    /// The values whose writes the summary hasn't seen.
+    type Delta = Vec<(VersionVector<K>, T)>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        for (index, (version, _)) in self.values.iter().enumerate() {
+            if version.is_exhausted() {
+                return Err(ScaffoldError::verification_failed(
+                    "a version vector counter has reached u64::MAX",
+                ));
+            }
+            for (other, _) in &self.values[index + 1..] {
+                if other <= version {
+                    return Err(ScaffoldError::verification_failed(
+                        "values are not sorted by version vector",
+                    ));
+                }
+                if version.causal_cmp(other).is_some() {
+                    return Err(ScaffoldError::verification_failed(
+                        "a value has been superseded by another value",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.version_vector()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let delta: Vec<_> = self
+            .values
+            .iter()
+            .filter(|(version, _)| !old_state_summary.dominates(version))
+            .cloned()
+            .collect();
+        if delta.is_empty() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        if let Some(delta) = delta {
+            // Checked up front so that a rejected delta leaves the register unchanged
+            if delta.iter().any(|(version, _)| version.is_exhausted()) {
+                return Err(ScaffoldError::invalid_delta(
+                    "a version vector counter has reached u64::MAX",
+                ));
+            }
+            for (version, value) in delta {
+                self.insert(version.clone(), value.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Applying values doesn't depend on their order, so the deltas can simply be joined.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        let mut composed = first.clone();
+        for (version, value) in second {
+            // A version vector identifies the write that produced the value
+            if !composed.iter().any(|(existing, _)| existing == version) {
+                composed.push((version.clone(), value.clone()));
+            }
+        }
+        Some(composed)
+    }
+}
@@ -0,0 +1,116 @@
+//! Versioned state schemas, so a contract's types can change without breaking peers that still
+//! send states, summaries and deltas in an older format.
+//!
+//! Each version of a root state is its own type. The first is declared with
+//! `#[composable(version = 1)]`, and every later one names the type it replaces:
+//!
+//! ```ignore
+//! #[composable(version = 1)]
+//! #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+//! pub struct ChatRoomV1 {
+//!     messages: AppendLog<Message>,
+//! }
+//!
+//! #[composable(version = 2, previous = "ChatRoomV1")]
+//! #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+//! pub struct ChatRoom {
+//!     messages: AppendLog<Message>,
+//!     members: OrSet<Member>,
+//! }
+//!
+//! impl Migrate for ChatRoomV1 {
+//!     type Next = ChatRoom;
+//!     // ...
+//! }
+//! ```
+//!
+//! The macro implements [`Versioned`], and the old type implements [`Migrate`] to upgrade its
+//! state, summary and delta to the next version. [`Migrating`] encodes with the schema version in
+//! the blob's [`Header`] and on load migrates whatever version it finds, one step at a time, to
+//! the latest one. Use it as the codec of a [`ContractAdapter`](crate::contract::ContractAdapter):
+//! `ContractAdapter<ChatRoom, Migrating<Cbor>>`.
+
+use crate::codec::{decode_body, Codec, CodecError, Format, Header, StateCodec};
+use crate::ComposableState;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+
+/// A state type with a schema version, implemented by `#[composable(version = N)]`.
+///
+/// The `read_*` functions decode a body written at `schema`, which is either this type's
+/// `VERSION` or, migrated along the way, an earlier one.
+pub trait Versioned: ComposableState + Serialize + DeserializeOwned {
+    const VERSION: u32;
+
+    fn read_state(format: Format, schema: u32, body: &[u8]) -> Result<Self, CodecError>;
+    fn read_summary(format: Format, schema: u32, body: &[u8]) -> Result<Self::Summary, CodecError>;
+    fn read_delta(
+        format: Format,
+        schema: u32,
+        body: &[u8],
+    ) -> Result<Option<Self::Delta>, CodecError>;
+}
+
+/// Upgrades a version of a state to the next one, implemented on the older type.
+pub trait Migrate: Versioned {
+    type Next: Versioned;
+
+    fn migrate_state(self) -> Self::Next;
+    fn migrate_summary(summary: Self::Summary) -> <Self::Next as ComposableState>::Summary;
+    fn migrate_delta(delta: Self::Delta) -> <Self::Next as ComposableState>::Delta;
+}
+
+/// Decodes `body` if it was written at `version`, otherwise hands it to `previous`. Used by the
+/// `Versioned` implementations `#[composable]` generates.
+#[doc(hidden)]
+pub fn read_or_migrate<V: DeserializeOwned>(
+    format: Format,
+    schema: u32,
+    version: u32,
+    body: &[u8],
+    previous: impl FnOnce() -> Result<V, CodecError>,
+) -> Result<V, CodecError> {
+    match schema.cmp(&version) {
+        Ordering::Equal => decode_body(format, body),
+        Ordering::Less => previous(),
+        Ordering::Greater => Err(CodecError::UnsupportedSchema(schema)),
+    }
+}
+
+/// Encodes states, summaries and deltas with `C`, recording the schema version of `T` in the
+/// header, and migrates older ones on load.
+///
+/// Blobs without a schema version, such as those written by `C` itself before `T` was
+/// versioned, are read as version 1.
+pub struct Migrating<C>(PhantomData<C>);
+
+impl<T: Versioned, C: Codec> StateCodec<T> for Migrating<C> {
+    fn encode_state(state: &T) -> Result<Vec<u8>, CodecError> {
+        C::encode_versioned(state, T::VERSION)
+    }
+
+    fn decode_state(bytes: &[u8]) -> Result<T, CodecError> {
+        let (header, body) = Header::read(bytes)?;
+        T::read_state(header.format, header.schema.unwrap_or(1), body)
+    }
+
+    fn encode_summary(summary: &T::Summary) -> Result<Vec<u8>, CodecError> {
+        C::encode_versioned(summary, T::VERSION)
+    }
+
+    fn decode_summary(bytes: &[u8]) -> Result<T::Summary, CodecError> {
+        let (header, body) = Header::read(bytes)?;
+        T::read_summary(header.format, header.schema.unwrap_or(1), body)
+    }
+
+    fn encode_delta(delta: &Option<T::Delta>) -> Result<Vec<u8>, CodecError> {
+        C::encode_versioned(delta, T::VERSION)
+    }
+
+    fn decode_delta(bytes: &[u8]) -> Result<Option<T::Delta>, CodecError> {
+        let (header, body) = Header::read(bytes)?;
+        T::read_delta(header.format, header.schema.unwrap_or(1), body)
+    }
+}
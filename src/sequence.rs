@@ -0,0 +1,487 @@
+use crate::version_vector::VersionVector;
+use crate::{ComposableState, InvertibleState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Debug, Display};
+use std::marker::PhantomData;
+
+/// Identifies an operation on an [`Rga`], ordered by Lamport timestamp and then by actor.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OpId<A> {
+    pub lamport: u64,
+    pub actor: A,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum RgaOp<T, A> {
+    /// Inserts `value` immediately after `origin`, or at the start if `origin` is `None`.
+    Insert {
+        id: OpId<A>,
+        origin: Option<OpId<A>>,
+        value: T,
+    },
+    /// Deletes the element inserted by `target`.
+    Delete { id: OpId<A>, target: OpId<A> },
+}
+
+impl<T, A> RgaOp<T, A> {
+    pub fn id(&self) -> &OpId<A> {
+        match self {
+            RgaOp::Insert { id, .. } | RgaOp::Delete { id, .. } => id,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct RgaNode<T, A> {
+    id: OpId<A>,
+    origin: Option<OpId<A>>,
+    value: T,
+    deleted: bool,
+}
+
+/// A replicated growable array, a sequence that accepts concurrent inserts and deletes.
+///
+/// Every insert is placed after the element it was made next to, with concurrent inserts at the
+/// same position ordered by their [`OpId`], so every peer ends up with the same order. Deleted
+/// elements are kept as tombstones so later inserts can still be positioned relative to them.
+///
+/// Each actor's operations carry increasing Lamport timestamps, so the summary is a
+/// [`VersionVector`] holding the latest timestamp seen from each actor, and stays the same size
+/// however long the sequence grows. `Rga<char, A>` implements `Display` for collaborative text.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(from = "RawRga<T, A>")]
+#[serde(bound(deserialize = "T: Deserialize<'de>, A: Ord + Clone + Deserialize<'de>"))]
+pub struct Rga<T, A, S = (), P = ()> {
+    /// Every inserted element in sequence order, including deleted ones.
+    nodes: Vec<RgaNode<T, A>>,
+    /// `(id, target)` of every delete, sorted by id.
+    deletions: Vec<(OpId<A>, OpId<A>)>,
+    /// The index in `nodes` of every element, rebuilt on load.
+    #[serde(skip)]
+    positions: BTreeMap<OpId<A>, usize>,
+    #[serde(skip)]
+    _context: PhantomData<fn() -> (S, P)>,
+}
+
+/// The serialized form of an [`Rga`], without the index of its elements.
+#[derive(Deserialize)]
+struct RawRga<T, A> {
+    nodes: Vec<RgaNode<T, A>>,
+    deletions: Vec<(OpId<A>, OpId<A>)>,
+}
+
+impl<T, A: Ord + Clone, S, P> From<RawRga<T, A>> for Rga<T, A, S, P> {
+    fn from(RawRga { nodes, deletions }: RawRga<T, A>) -> Self {
+        let positions = nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (node.id.clone(), index))
+            .collect();
+        Rga {
+            nodes,
+            deletions,
+            positions,
+            _context: PhantomData,
+        }
+    }
+}
+
+impl<T, A, S, P> Default for Rga<T, A, S, P> {
+    fn default() -> Self {
+        Rga {
+            nodes: Vec::new(),
+            deletions: Vec::new(),
+            positions: BTreeMap::new(),
+            _context: PhantomData,
+        }
+    }
+}
+
+impl<T: Clone, A: Ord + Clone, S, P> Rga<T, A, S, P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts `value` at `index` among the elements that haven't been deleted.
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, actor: A, index: usize, value: T) {
+        assert!(index <= self.len(), "insertion index out of bounds");
+        let origin = index
+            .checked_sub(1)
+            .map(|previous| self.visible_node(previous).id.clone());
+        let id = self.next_id(actor);
+        self.integrate_insert(id, origin, value)
+            .expect("origin of a local insert exists");
+    }
+
+    pub fn push(&mut self, actor: A, value: T) {
+        self.insert(actor, self.len(), value);
+    }
+
+    /// Deletes the element at `index` among the elements that haven't been deleted.
+    ///
+    /// Panics if `index >= len`.
+    pub fn delete(&mut self, actor: A, index: usize) -> T {
+        assert!(index < self.len(), "deletion index out of bounds");
+        let node = self.visible_node(index);
+        let (target, value) = (node.id.clone(), node.value.clone());
+        let id = self.next_id(actor);
+        self.integrate_delete(id, target)
+            .expect("target of a local delete exists");
+        value
+    }
+
+    /// The number of elements that haven't been deleted.
+    pub fn len(&self) -> usize {
+        self.nodes.iter().filter(|node| !node.deleted).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.nodes
+            .iter()
+            .filter(|node| !node.deleted)
+            .map(|node| &node.value)
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+
+    /// The latest Lamport timestamp seen from each actor.
+    pub fn version_vector(&self) -> VersionVector<A> {
+        let mut version = VersionVector::new();
+        for id in self.op_ids() {
+            version.observe(id.actor.clone(), id.lamport);
+        }
+        version
+    }
+
+    fn op_ids(&self) -> impl Iterator<Item = &OpId<A>> {
+        self.nodes
+            .iter()
+            .map(|node| &node.id)
+            .chain(self.deletions.iter().map(|(id, _)| id))
+    }
+
+    fn next_id(&self, actor: A) -> OpId<A> {
+        let lamport = self.op_ids().map(|id| id.lamport).max().unwrap_or(0) + 1;
+        OpId { lamport, actor }
+    }
+
+    fn visible_node(&self, index: usize) -> &RgaNode<T, A> {
+        self.nodes
+            .iter()
+            .filter(|node| !node.deleted)
+            .nth(index)
+            .expect("index is within bounds")
+    }
+
+    fn position(&self, id: &OpId<A>) -> Option<usize> {
+        self.positions.get(id).copied()
+    }
+
+    fn integrate_insert(
+        &mut self,
+        id: OpId<A>,
+        origin: Option<OpId<A>>,
+        value: T,
+    ) -> Result<(), ScaffoldError> {
+        if self.position(&id).is_some() {
+            return Ok(());
+        }
+        let mut index = match &origin {
+            Some(origin) => {
+                self.position(origin).ok_or_else(|| {
+                    ScaffoldError::invalid_delta("insert after an element that doesn't exist")
+                })? + 1
+            }
+            None => 0,
+        };
+        // Concurrent inserts at the same position, and everything inserted after them, have
+        // greater ids and go first
+        while index < self.nodes.len() && self.nodes[index].id > id {
+            index += 1;
+        }
+        for node in &self.nodes[index..] {
+            *self
+                .positions
+                .get_mut(&node.id)
+                .expect("every node has a position") += 1;
+        }
+        self.positions.insert(id.clone(), index);
+        self.nodes.insert(
+            index,
+            RgaNode {
+                id,
+                origin,
+                value,
+                deleted: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn integrate_delete(&mut self, id: OpId<A>, target: OpId<A>) -> Result<(), ScaffoldError> {
+        let index = match self
+            .deletions
+            .binary_search_by(|(existing, _)| existing.cmp(&id))
+        {
+            Ok(_) => return Ok(()),
+            Err(index) => index,
+        };
+        let position = self.position(&target).ok_or_else(|| {
+            ScaffoldError::invalid_delta("delete of an element that doesn't exist")
+        })?;
+        self.nodes[position].deleted = true;
+        self.deletions.insert(index, (id, target));
+        Ok(())
+    }
+}
+
+impl<T, A, S, P> ComposableState for Rga<T, A, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    A: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    type Summary = VersionVector<A>;
+    /// The operations the summary hasn't seen, in Lamport order so that every element is
+    /// inserted before anything that refers to it.
+    type Delta = Vec<RgaOp<T, A>>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        let targets: BTreeSet<&OpId<A>> = self.deletions.iter().map(|(_, target)| target).collect();
+        // The elements before the one being checked
+        let mut preceding = BTreeSet::new();
+        for node in &self.nodes {
+            if let Some(origin) = &node.origin {
+                if !preceding.contains(origin) {
+                    return Err(ScaffoldError::verification_failed(format!(
+                        "element {:?} is not positioned after its origin",
+                        node.id
+                    )));
+                }
+            }
+            if !preceding.insert(&node.id) {
+                return Err(ScaffoldError::verification_failed(format!(
+                    "element {:?} was inserted twice",
+                    node.id
+                )));
+            }
+            let deleted = targets.contains(&node.id);
+            if node.deleted != deleted {
+                return Err(ScaffoldError::verification_failed(format!(
+                    "deletion of element {:?} is inconsistent",
+                    node.id
+                )));
+            }
+        }
+        if !self.deletions.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            return Err(ScaffoldError::verification_failed(
+                "deletions are not sorted by id",
+            ));
+        }
+        if let Some((id, _)) = self
+            .deletions
+            .iter()
+            .find(|(_, target)| !preceding.contains(target))
+        {
+            return Err(ScaffoldError::verification_failed(format!(
+                "deletion {:?} refers to an element that doesn't exist",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.version_vector()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let unseen = |id: &OpId<A>| id.lamport > old_state_summary.get(&id.actor);
+        let mut delta: Vec<RgaOp<T, A>> = self
+            .nodes
+            .iter()
+            .filter(|node| unseen(&node.id))
+            .map(|node| RgaOp::Insert {
+                id: node.id.clone(),
+                origin: node.origin.clone(),
+                value: node.value.clone(),
+            })
+            .chain(
+                self.deletions
+                    .iter()
+                    .filter(|(id, _)| unseen(id))
+                    .map(|(id, target)| RgaOp::Delete {
+                        id: id.clone(),
+                        target: target.clone(),
+                    }),
+            )
+            .collect();
+        if delta.is_empty() {
+            return None;
+        }
+        delta.sort_by(|a, b| a.id().cmp(b.id()));
+        Some(delta)
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        let Some(delta) = delta else {
+            return Ok(());
+        };
+        let mut ops: Vec<&RgaOp<T, A>> = delta.iter().collect();
+        ops.sort_by(|a, b| a.id().cmp(b.id()));
+        for op in ops {
+            match op {
+                RgaOp::Insert { id, origin, value } => {
+                    self.integrate_insert(id.clone(), origin.clone(), value.clone())?
+                }
+                RgaOp::Delete { id, target } => {
+                    self.integrate_delete(id.clone(), target.clone())?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Operations are applied in id order whichever delta they came in, so the deltas can
+    /// simply be joined.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        let mut composed = first.clone();
+        for op in second {
+            if !composed.iter().any(|existing| existing.id() == op.id()) {
+                composed.push(op.clone());
+            }
+        }
+        composed.sort_by(|a, b| a.id().cmp(b.id()));
+        Some(composed)
+    }
+}
+
+impl<T, A, S, P> InvertibleState for Rga<T, A, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    A: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    /// Deletes the elements the delta inserted and inserts the values it deleted again, each
+    /// right after the tombstone of the element it replaces so it shows up in the same place.
+    ///
+    /// Deleted elements can't be revived, so the inverse is made of new operations, attributed
+    /// to the actors of the operations they undo, with Lamport timestamps after every operation
+    /// in the state and the delta. An element both inserted and deleted by the delta is left
+    /// alone.
+    fn invert_delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Self::Delta,
+    ) -> Result<Self::Delta, ScaffoldError> {
+        let mut ops: Vec<&RgaOp<T, A>> = delta.iter().collect();
+        ops.sort_by(|a, b| a.id().cmp(b.id()));
+        // The elements the delta added and the ones it took away, ignoring operations the
+        // state had already seen
+        let inserted: BTreeSet<&OpId<A>> = ops
+            .iter()
+            .filter_map(|op| match op {
+                RgaOp::Insert { id, .. } if self.position(id).is_none() => Some(id),
+                _ => None,
+            })
+            .collect();
+        let deleted: BTreeSet<&OpId<A>> = ops
+            .iter()
+            .filter_map(|op| match op {
+                RgaOp::Delete { target, .. } => Some(target),
+                RgaOp::Insert { .. } => None,
+            })
+            .filter(|target| {
+                self.position(target)
+                    .is_none_or(|position| !self.nodes[position].deleted)
+            })
+            .collect();
+
+        let mut lamport = self
+            .op_ids()
+            .chain(ops.iter().map(|op| op.id()))
+            .map(|id| id.lamport)
+            .max()
+            .unwrap_or(0);
+        let mut next_id = |actor: &A| {
+            lamport += 1;
+            OpId {
+                lamport,
+                actor: actor.clone(),
+            }
+        };
+        let mut restored = BTreeSet::new();
+        let mut inverse = Vec::new();
+        for op in ops {
+            match op {
+                RgaOp::Insert { id, .. } if inserted.contains(id) && !deleted.contains(id) => {
+                    inverse.push(RgaOp::Delete {
+                        id: next_id(&id.actor),
+                        target: id.clone(),
+                    });
+                }
+                RgaOp::Delete { id, target }
+                    if deleted.contains(target) && !inserted.contains(target) =>
+                {
+                    let position = self.position(target).ok_or_else(|| {
+                        ScaffoldError::invalid_delta("delete of an element that doesn't exist")
+                    })?;
+                    // Only the first delete of an element brings it back
+                    if !restored.insert(target) {
+                        continue;
+                    }
+                    let value = self.nodes[position].value.clone();
+                    inverse.push(RgaOp::Insert {
+                        id: next_id(&id.actor),
+                        origin: Some(target.clone()),
+                        value,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(inverse)
+    }
+}
+
+impl<A, S, P> Display for Rga<char, A, S, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in self.nodes.iter().filter(|node| !node.deleted) {
+            write!(f, "{}", node.value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,595 @@
+use crate::util::{hash_serialized, FastHash};
+use crate::{ComposableState, InvertibleState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// An observed-remove set.
+///
+/// Every addition is tagged with a caller-supplied nonce and identified by the [`FastHash`] of
+/// the element and nonce. Removing an element tombstones only the additions that have been
+/// observed, so an addition made concurrently with a removal survives it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OrSet<T, S = (), P = ()> {
+    /// Live additions by id, along with the nonce the id was computed from.
+    elements: BTreeMap<FastHash, (u64, T)>,
+    /// Ids of removed additions.
+    tombstones: BTreeSet<FastHash>,
+    #[serde(skip)]
+    _context: PhantomData<fn() -> (S, P)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OrSetSummary {
+    pub elements: BTreeSet<FastHash>,
+    pub tombstones: BTreeSet<FastHash>,
+}
+
+/// The additions and tombstones missing from the summary the delta was computed against.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OrSetDelta<T> {
+    /// `(nonce, element)` pairs, ids are recomputed by the receiver.
+    pub additions: Vec<(u64, T)>,
+    pub tombstones: Vec<FastHash>,
+}
+
+impl<T, S, P> Default for OrSet<T, S, P> {
+    fn default() -> Self {
+        OrSet {
+            elements: BTreeMap::new(),
+            tombstones: BTreeSet::new(),
+            _context: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize + PartialEq, S, P> OrSet<T, S, P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The id of the addition of `element` tagged with `nonce`.
+    pub fn addition_id(element: &T, nonce: u64) -> FastHash {
+        hash_serialized(&(element, nonce))
+    }
+
+    /// Adds `element`, returning whether the set changed.
+    ///
+    /// `nonce` must differ between additions of the same element, a timestamp or random number
+    /// will do. Re-adding an element with the nonce of an addition that has since been removed
+    /// has no effect.
+    pub fn add(&mut self, element: T, nonce: u64) -> bool {
+        let id = Self::addition_id(&element, nonce);
+        if self.tombstones.contains(&id) || self.elements.contains_key(&id) {
+            return false;
+        }
+        self.elements.insert(id, (nonce, element));
+        true
+    }
+
+    /// Removes every observed addition of `element`, returning whether it was present.
+    pub fn remove(&mut self, element: &T) -> bool {
+        let ids: Vec<FastHash> = self
+            .elements
+            .iter()
+            .filter(|(_, (_, existing))| existing == element)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &ids {
+            self.elements.remove(id);
+            self.tombstones.insert(*id);
+        }
+        !ids.is_empty()
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.elements
+            .values()
+            .any(|(_, existing)| existing == element)
+    }
+
+    /// The distinct elements in the set, in an order that is the same on every peer.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let mut seen = BTreeSet::new();
+        self.elements
+            .values()
+            .filter(move |(_, element)| seen.insert(hash_serialized(element)))
+            .map(|(_, element)| element)
+    }
+
+    /// The number of distinct elements.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+impl<T, S, P> ComposableState for OrSet<T, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug + PartialEq,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    type Summary = OrSetSummary;
+    type Delta = OrSetDelta<T>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        for (id, (nonce, element)) in &self.elements {
+            if *id != Self::addition_id(element, *nonce) {
+                return Err(ScaffoldError::verification_failed(format!(
+                    "addition {:?} does not match its element and nonce",
+                    id
+                )));
+            }
+        }
+        if let Some(id) = self
+            .tombstones
+            .iter()
+            .find(|id| self.elements.contains_key(id))
+        {
+            return Err(ScaffoldError::verification_failed(format!(
+                "tombstone {:?} refers to a live addition",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        OrSetSummary {
+            elements: self.elements.keys().copied().collect(),
+            tombstones: self.tombstones.clone(),
+        }
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let additions: Vec<(u64, T)> = self
+            .elements
+            .iter()
+            .filter(|(id, _)| {
+                !old_state_summary.elements.contains(id)
+                    && !old_state_summary.tombstones.contains(id)
+            })
+            .map(|(_, addition)| addition.clone())
+            .collect();
+        let tombstones: Vec<FastHash> = self
+            .tombstones
+            .difference(&old_state_summary.tombstones)
+            .copied()
+            .collect();
+        if additions.is_empty() && tombstones.is_empty() {
+            None
+        } else {
+            Some(OrSetDelta {
+                additions,
+                tombstones,
+            })
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        if let Some(delta) = delta {
+            // Tombstones first, so an addition removed within the same delta stays removed
+            for id in &delta.tombstones {
+                self.elements.remove(id);
+                self.tombstones.insert(*id);
+            }
+            for (nonce, element) in &delta.additions {
+                self.add(element.clone(), *nonce);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tombstones are applied before additions, and a removed addition never comes back, so
+    /// the deltas can simply be joined.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        let mut composed = first.clone();
+        for (nonce, element) in &second.additions {
+            let id = Self::addition_id(element, *nonce);
+            if !composed
+                .additions
+                .iter()
+                .any(|(nonce, element)| Self::addition_id(element, *nonce) == id)
+            {
+                composed.additions.push((*nonce, element.clone()));
+            }
+        }
+        for id in &second.tombstones {
+            if !composed.tombstones.contains(id) {
+                composed.tombstones.push(*id);
+            }
+        }
+        Some(composed)
+    }
+}
+
+impl<T, S, P> InvertibleState for OrSet<T, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug + PartialEq,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    /// Tombstones the additions that took effect and re-adds the elements that were removed.
+    ///
+    /// A removed addition can't be revived, so an element comes back as a new addition whose
+    /// nonce is derived from the id of the one that was removed.
+    fn invert_delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Self::Delta,
+    ) -> Result<Self::Delta, ScaffoldError> {
+        let tombstones = delta
+            .additions
+            .iter()
+            .map(|(nonce, element)| Self::addition_id(element, *nonce))
+            .filter(|id| {
+                !self.elements.contains_key(id)
+                    && !self.tombstones.contains(id)
+                    && !delta.tombstones.contains(id)
+            })
+            .collect();
+        let additions = delta
+            .tombstones
+            .iter()
+            .filter_map(|id| self.elements.get(id).map(|(_, element)| (id, element)))
+            .map(|(id, element)| (hash_serialized(id).0 as u64, element.clone()))
+            .collect();
+        Ok(OrSetDelta {
+            additions,
+            tombstones,
+        })
+    }
+}
+
+/// A grow-only set, elements are identified by the [`hash_serialized`] of their value.
+///
+/// It doesn't implement [`InvertibleState`], since an element can't be removed once added. Use
+/// an [`OrSet`] for elements that can be undone.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GSet<T, S = (), P = ()> {
+    elements: BTreeMap<FastHash, T>,
+    #[serde(skip)]
+    _context: PhantomData<fn() -> (S, P)>,
+}
+
+impl<T, S, P> Default for GSet<T, S, P> {
+    fn default() -> Self {
+        GSet {
+            elements: BTreeMap::new(),
+            _context: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize, S, P> GSet<T, S, P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `element`, returning whether it was not already present.
+    pub fn insert(&mut self, element: T) -> bool {
+        let id = hash_serialized(&element);
+        if self.elements.contains_key(&id) {
+            return false;
+        }
+        self.elements.insert(id, element);
+        true
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.elements.contains_key(&hash_serialized(element))
+    }
+
+    /// The elements in the set, in an order that is the same on every peer.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+impl<T, S, P> ComposableState for GSet<T, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    type Summary = BTreeSet<FastHash>;
+    /// The elements missing from the summary.
+    type Delta = Vec<T>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        verify_element_ids(&self.elements)
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.elements.keys().copied().collect()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let delta: Vec<T> = self
+            .elements
+            .iter()
+            .filter(|(id, _)| !old_state_summary.contains(id))
+            .map(|(_, element)| element.clone())
+            .collect();
+        if delta.is_empty() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        if let Some(delta) = delta {
+            for element in delta {
+                self.insert(element.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        let ids: BTreeSet<FastHash> = first.iter().map(hash_serialized).collect();
+        let mut composed = first.clone();
+        composed.extend(
+            second
+                .iter()
+                .filter(|element| !ids.contains(&hash_serialized(*element)))
+                .cloned(),
+        );
+        Some(composed)
+    }
+}
+
+/// A set where an element can be added and removed, but never re-added once removed.
+///
+/// Removed elements are dropped, only their [`FastHash`] is kept to prevent re-addition. It
+/// doesn't implement [`InvertibleState`], since undoing a removal would re-add the element.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TwoPhaseSet<T, S = (), P = ()> {
+    elements: BTreeMap<FastHash, T>,
+    removed: BTreeSet<FastHash>,
+    #[serde(skip)]
+    _context: PhantomData<fn() -> (S, P)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TwoPhaseSetSummary {
+    pub elements: BTreeSet<FastHash>,
+    pub removed: BTreeSet<FastHash>,
+}
+
+/// The elements and removals missing from the summary the delta was computed against.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TwoPhaseSetDelta<T> {
+    pub elements: Vec<T>,
+    pub removed: Vec<FastHash>,
+}
+
+impl<T, S, P> Default for TwoPhaseSet<T, S, P> {
+    fn default() -> Self {
+        TwoPhaseSet {
+            elements: BTreeMap::new(),
+            removed: BTreeSet::new(),
+            _context: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize, S, P> TwoPhaseSet<T, S, P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `element`, returning whether the set changed. Removed elements are never re-added.
+    pub fn insert(&mut self, element: T) -> bool {
+        let id = hash_serialized(&element);
+        if self.removed.contains(&id) || self.elements.contains_key(&id) {
+            return false;
+        }
+        self.elements.insert(id, element);
+        true
+    }
+
+    /// Removes `element` permanently, whether or not it has been added yet. Returns whether it
+    /// was present.
+    pub fn remove(&mut self, element: &T) -> bool {
+        let id = hash_serialized(element);
+        self.removed.insert(id);
+        self.elements.remove(&id).is_some()
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.elements.contains_key(&hash_serialized(element))
+    }
+
+    /// Whether `element` has been removed and can't be added again.
+    pub fn is_removed(&self, element: &T) -> bool {
+        self.removed.contains(&hash_serialized(element))
+    }
+
+    /// The elements in the set, in an order that is the same on every peer.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+impl<T, S, P> ComposableState for TwoPhaseSet<T, S, P>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug,
+    S: Serialize + DeserializeOwned + Clone + Debug,
+    P: Serialize + DeserializeOwned + Clone + Debug,
+{
+    type ParentState = S;
+    type Summary = TwoPhaseSetSummary;
+    type Delta = TwoPhaseSetDelta<T>;
+    type Parameters = P;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        verify_element_ids(&self.elements)?;
+        if let Some(id) = self
+            .removed
+            .iter()
+            .find(|id| self.elements.contains_key(id))
+        {
+            return Err(ScaffoldError::verification_failed(format!(
+                "element {:?} is both present and removed",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        TwoPhaseSetSummary {
+            elements: self.elements.keys().copied().collect(),
+            removed: self.removed.clone(),
+        }
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let elements: Vec<T> = self
+            .elements
+            .iter()
+            .filter(|(id, _)| {
+                !old_state_summary.elements.contains(id) && !old_state_summary.removed.contains(id)
+            })
+            .map(|(_, element)| element.clone())
+            .collect();
+        let removed: Vec<FastHash> = self
+            .removed
+            .difference(&old_state_summary.removed)
+            .copied()
+            .collect();
+        if elements.is_empty() && removed.is_empty() {
+            None
+        } else {
+            Some(TwoPhaseSetDelta { elements, removed })
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        if let Some(delta) = delta {
+            for id in &delta.removed {
+                self.elements.remove(id);
+                self.removed.insert(*id);
+            }
+            for element in &delta.elements {
+                self.insert(element.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Removals are applied before elements, and a removed element is never re-added, so the
+    /// deltas can simply be joined.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        let ids: BTreeSet<FastHash> = first.elements.iter().map(hash_serialized).collect();
+        let mut composed = first.clone();
+        composed.elements.extend(
+            second
+                .elements
+                .iter()
+                .filter(|element| !ids.contains(&hash_serialized(*element)))
+                .cloned(),
+        );
+        for id in &second.removed {
+            if !composed.removed.contains(id) {
+                composed.removed.push(*id);
+            }
+        }
+        Some(composed)
+    }
+}
+
+fn verify_element_ids<T: Serialize>(elements: &BTreeMap<FastHash, T>) -> Result<(), ScaffoldError> {
+    for (id, element) in elements {
+        if *id != hash_serialized(element) {
+            return Err(ScaffoldError::verification_failed(format!(
+                "element {:?} does not match its hash",
+                id
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,313 @@
+//! Signed states, for checking who wrote a value without doing it by hand in every `verify`.
+//!
+//! [`Signed<T>`] holds a value together with the public key of its author and their signature
+//! over it. It implements [`ComposableState`] by delegating to `T`, which has to be a
+//! [`WholeValue`], and only accepts a state or delta signed by a key that its [`KeyPolicy`]
+//! authorizes, taken from the `Parameters` or the `ParentState`. Signature schemes implement [`Verifier`] and [`Signer`], the `ed25519` feature
+//! adds [`Ed25519`].
+
+use crate::util::{compose_optional_deltas, hash_serialized, FastHash};
+use crate::{ComposableState, ScaffoldError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// A signature scheme, checking signatures made by a [`Signer`].
+pub trait Verifier {
+    type PublicKey: Serialize + DeserializeOwned + Clone + Debug + PartialEq;
+    type Signature: Serialize + DeserializeOwned + Clone + Debug + PartialEq;
+
+    fn verify(key: &Self::PublicKey, message: &[u8], signature: &Self::Signature) -> bool;
+}
+
+/// A private key of the signature scheme `V`.
+pub trait Signer<V: Verifier> {
+    fn public_key(&self) -> V::PublicKey;
+    fn sign(&self, message: &[u8]) -> V::Signature;
+}
+
+/// A list of the keys allowed to sign, such as the contract's parameters.
+pub trait AuthorizedKeys<K> {
+    fn is_authorized(&self, key: &K) -> bool;
+}
+
+impl<K: PartialEq> AuthorizedKeys<K> for Vec<K> {
+    fn is_authorized(&self, key: &K) -> bool {
+        self.contains(key)
+    }
+}
+
+impl<K: Ord> AuthorizedKeys<K> for BTreeSet<K> {
+    fn is_authorized(&self, key: &K) -> bool {
+        self.contains(key)
+    }
+}
+
+/// Where a [`Signed`] state looks up the keys allowed to sign it.
+pub trait KeyPolicy<S, P, K> {
+    fn is_authorized(parent_state: &S, parameters: &P, key: &K) -> bool;
+}
+
+/// The keys listed in the `Parameters`, fixed for the lifetime of the contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FromParameters;
+
+impl<S, P: AuthorizedKeys<K>, K> KeyPolicy<S, P, K> for FromParameters {
+    fn is_authorized(_parent_state: &S, parameters: &P, key: &K) -> bool {
+        parameters.is_authorized(key)
+    }
+}
+
+/// The keys listed in the `ParentState`, such as the members of a room, which can change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FromParentState;
+
+impl<S: AuthorizedKeys<K>, P, K> KeyPolicy<S, P, K> for FromParentState {
+    fn is_authorized(parent_state: &S, _parameters: &P, key: &K) -> bool {
+        parent_state.is_authorized(key)
+    }
+}
+
+/// A state that changes as a whole: applying a delta either leaves it unchanged or replaces it
+/// with the state the delta was computed from, never a mix of both, so merging two states ends
+/// in one of them. [`LwwRegister`](crate::register::LwwRegister) is one.
+///
+/// Only such states can be [`Signed`], since the signature of a merged state has to be the
+/// signature of one of the two. Two authors changing a set concurrently would each produce a
+/// set the other never signed:
+///
+/// ```compile_fail,E0277
+/// use freenet_scaffold::set::GSet;
+/// use freenet_scaffold::signed::Signed;
+/// use freenet_scaffold::ComposableState;
+///
+/// fn check<T: ComposableState>() {}
+/// # struct Scheme;
+/// # impl freenet_scaffold::signed::Verifier for Scheme {
+/// #     type PublicKey = u8;
+/// #     type Signature = u8;
+/// #     fn verify(_: &u8, _: &[u8], _: &u8) -> bool { true }
+/// # }
+/// check::<Signed<GSet<String, (), Vec<u8>>, Scheme>>();
+/// ```
+pub trait WholeValue: ComposableState {}
+
+/// A value signed by an authorized key.
+///
+/// Every change is signed as a whole: a delta carries the signature over the value it produces,
+/// and is rejected unless that signature is valid. `T` is a [`WholeValue`], so when authors
+/// change it concurrently, every peer ends up with the value that wins in `T` and the signature
+/// of its author, and a delta that loses is ignored. When the value is the same, the signature
+/// with the lowest [`hash_serialized`] is kept so peers converge.
+///
+/// It doesn't implement [`InvertibleState`](crate::InvertibleState), since the inverse of a
+/// delta has to be signed and `invert_delta` has no key to sign it with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned"))]
+pub struct Signed<T, V: Verifier, K = FromParameters> {
+    value: T,
+    signer: V::PublicKey,
+    signature: V::Signature,
+    #[serde(skip)]
+    _policy: PhantomData<fn() -> K>,
+}
+
+/// The change to the value, if any, and the signature over the value it produces.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SignedDelta<D, PublicKey, Signature> {
+    pub value: Option<D>,
+    pub signer: PublicKey,
+    pub signature: Signature,
+}
+
+impl<T: ComposableState + Serialize, V: Verifier, K> Signed<T, V, K> {
+    /// `value` signed by `signer` for the contract with `parameters`.
+    pub fn new(value: T, signer: &impl Signer<V>, parameters: &T::Parameters) -> Self {
+        let signature = signer.sign(&signed_bytes(SIGNED_PURPOSE, parameters, &value));
+        Signed {
+            value,
+            signer: signer.public_key(),
+            signature,
+            _policy: PhantomData,
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    pub fn signer(&self) -> &V::PublicKey {
+        &self.signer
+    }
+
+    /// Replaces the value and signs it with `signer`.
+    pub fn set(&mut self, value: T, signer: &impl Signer<V>, parameters: &T::Parameters) {
+        *self = Signed::new(value, signer, parameters);
+    }
+
+    fn signature_hash(&self) -> FastHash {
+        hash_serialized(&self.signature)
+    }
+}
+
+/// Tags the signatures of [`Signed`] values, so they can't be passed off as signatures made
+/// for another purpose.
+const SIGNED_PURPOSE: &str = "freenet-scaffold/signed/v1";
+
+/// The bytes a signature covers: what it was made for, the parameters of the contract it was
+/// made in and the signed value. A signature can't be replayed into another type or contract,
+/// but it can into another field of the same type in the same contract, so values that must not
+/// be interchangeable should say what they are.
+pub(crate) fn signed_bytes<P: Serialize, T: Serialize>(
+    purpose: &str,
+    parameters: &P,
+    value: &T,
+) -> Vec<u8> {
+    bincode::serialize(&(purpose, parameters, value)).expect("value should be serializable")
+}
+
+/// Checks that `signer` is allowed to sign by the key policy `K`.
+fn check_authorized<T, V, K>(
+    signer: &V::PublicKey,
+    parent_state: &T::ParentState,
+    parameters: &T::Parameters,
+) -> Result<(), ScaffoldError>
+where
+    T: ComposableState,
+    V: Verifier,
+    K: KeyPolicy<T::ParentState, T::Parameters, V::PublicKey>,
+{
+    if K::is_authorized(parent_state, parameters, signer) {
+        Ok(())
+    } else {
+        Err(ScaffoldError::unauthorized(format!(
+            "{:?} is not authorized to sign",
+            signer
+        )))
+    }
+}
+
+impl<T, V, K> ComposableState for Signed<T, V, K>
+where
+    T: WholeValue + Serialize + DeserializeOwned + Clone + Debug,
+    V: Verifier,
+    K: KeyPolicy<T::ParentState, T::Parameters, V::PublicKey>,
+{
+    type ParentState = T::ParentState;
+    /// The summary of the value and the hash of its signature.
+    type Summary = (T::Summary, FastHash);
+    type Delta = SignedDelta<T::Delta, V::PublicKey, V::Signature>;
+    type Parameters = T::Parameters;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        check_authorized::<T, V, K>(&self.signer, parent_state, parameters)?;
+        let bytes = signed_bytes(SIGNED_PURPOSE, parameters, &self.value);
+        if !V::verify(&self.signer, &bytes, &self.signature) {
+            return Err(ScaffoldError::unauthorized("invalid signature"));
+        }
+        self.value.verify(parent_state, parameters)
+    }
+
+    fn summarize(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        (
+            self.value.summarize(parent_state, parameters),
+            self.signature_hash(),
+        )
+    }
+
+    fn delta(
+        &self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        let (old_value, old_signature) = old_state_summary;
+        let value = self.value.delta(parent_state, parameters, old_value);
+        // Without a change to the value, only a signature that would win is worth sending
+        if value.is_none() && self.signature_hash() >= *old_signature {
+            return None;
+        }
+        Some(SignedDelta {
+            value,
+            signer: self.signer.clone(),
+            signature: self.signature.clone(),
+        })
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        let Some(delta) = delta else {
+            return Ok(());
+        };
+        check_authorized::<T, V, K>(&delta.signer, parent_state, parameters)?;
+        let mut value = self.value.clone();
+        value.apply_delta(parent_state, parameters, &delta.value)?;
+        let bytes = signed_bytes(SIGNED_PURPOSE, parameters, &value);
+        let changed = bytes != signed_bytes(SIGNED_PURPOSE, parameters, &self.value);
+        if V::verify(&delta.signer, &bytes, &delta.signature) {
+            // For the same value, the lowest signature wins
+            if changed || hash_serialized(&delta.signature) < self.signature_hash() {
+                self.value = value;
+                self.signer = delta.signer.clone();
+                self.signature = delta.signature.clone();
+            }
+            return Ok(());
+        }
+        if changed {
+            return Err(ScaffoldError::unauthorized("invalid signature"));
+        }
+        // A delta that lost to the current value, or a duplicated or reordered one this state
+        // has since moved past, leaves the value unchanged, and its signature doesn't cover it.
+        // It is ignored rather than rejected, like a delta that lost in a register.
+        Ok(())
+    }
+
+    /// The changes to the value combined, with the signature of the second delta.
+    fn compose_deltas(first: &Self::Delta, second: &Self::Delta) -> Option<Self::Delta> {
+        Some(SignedDelta {
+            value: compose_optional_deltas::<T>(&first.value, &second.value)?,
+            signer: second.signer.clone(),
+            signature: second.signature.clone(),
+        })
+    }
+}
+
+/// Ed25519 signatures, with keys from `ed25519-dalek`.
+#[cfg(feature = "ed25519")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ed25519;
+
+#[cfg(feature = "ed25519")]
+impl Verifier for Ed25519 {
+    type PublicKey = ed25519_dalek::VerifyingKey;
+    type Signature = ed25519_dalek::Signature;
+
+    fn verify(key: &Self::PublicKey, message: &[u8], signature: &Self::Signature) -> bool {
+        key.verify_strict(message, signature).is_ok()
+    }
+}
+
+#[cfg(feature = "ed25519")]
+impl Signer<Ed25519> for ed25519_dalek::SigningKey {
+    fn public_key(&self) -> ed25519_dalek::VerifyingKey {
+        self.verifying_key()
+    }
+
+    fn sign(&self, message: &[u8]) -> ed25519_dalek::Signature {
+        ed25519_dalek::Signer::sign(self, message)
+    }
+}
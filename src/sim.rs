@@ -0,0 +1,256 @@
+//! A deterministic simulation of peers synchronizing a [`ComposableState`] over an unreliable
+//! network, enabled by the `sim` feature.
+//!
+//! Every round each peer sends its summary to a randomly chosen peer, which answers with a
+//! delta. Messages can be dropped, duplicated, delayed (and so reordered) or blocked by a
+//! partition, all decided by a seeded random number generator, so a run with the same seed,
+//! states and [`NetworkConditions`] always plays out the same way. This makes it possible to
+//! compare how quickly, and with how much traffic, different state designs converge.
+
+use crate::{ComposableState, ScaffoldError};
+use std::ops::Range;
+
+/// How unreliable the simulated network is.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct NetworkConditions {
+    /// Probability that a message is lost.
+    pub drop_rate: f64,
+    /// Probability that a message is delivered twice.
+    pub duplicate_rate: f64,
+    /// Every message takes between 1 and `1 + max_delay` rounds to arrive, so messages sent
+    /// later can overtake earlier ones.
+    pub max_delay: u64,
+    pub partitions: Vec<Partition>,
+}
+
+/// Splits the network into groups for a number of rounds. Messages sent while the partition is
+/// active only arrive if both peers are in the same group; peers not in any group form one more
+/// group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    pub rounds: Range<u64>,
+    pub groups: Vec<Vec<usize>>,
+}
+
+impl Partition {
+    fn separates(&self, round: u64, from: usize, to: usize) -> bool {
+        let group = |peer| self.groups.iter().position(|group| group.contains(&peer));
+        self.rounds.contains(&round) && group(from) != group(to)
+    }
+}
+
+/// What happened during a simulation.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct SimReport {
+    /// Rounds run, up to and including the one in which the peers converged.
+    pub rounds: u64,
+    pub converged: bool,
+    pub messages_sent: u64,
+    pub messages_dropped: u64,
+    /// Bincode-serialized size of every summary and delta sent, including dropped ones and
+    /// counting each duplicate once.
+    pub bytes_sent: u64,
+}
+
+enum Message<T: ComposableState> {
+    Summary(T::Summary),
+    Delta(T::Delta),
+}
+
+struct Envelope<T: ComposableState> {
+    deliver_at: u64,
+    from: usize,
+    to: usize,
+    message: Message<T>,
+}
+
+/// Peers holding a root state, one that is its own parent state like a `#[composable]` struct.
+pub struct Simulation<T: ComposableState<ParentState = T>> {
+    peers: Vec<T>,
+    parameters: T::Parameters,
+    conditions: NetworkConditions,
+    rng: SplitMix64,
+    in_flight: Vec<Envelope<T>>,
+    report: SimReport,
+}
+
+impl<T> Simulation<T>
+where
+    T: ComposableState<ParentState = T> + Clone + PartialEq,
+{
+    /// Starts a simulation with one peer per state.
+    pub fn new(
+        peers: Vec<T>,
+        parameters: T::Parameters,
+        conditions: NetworkConditions,
+        seed: u64,
+    ) -> Self {
+        Simulation {
+            peers,
+            parameters,
+            conditions,
+            rng: SplitMix64(seed),
+            in_flight: Vec::new(),
+            report: SimReport::default(),
+        }
+    }
+
+    pub fn peers(&self) -> &[T] {
+        &self.peers
+    }
+
+    /// Mutable access for local changes, which spread in the following rounds.
+    pub fn peer_mut(&mut self, peer: usize) -> &mut T {
+        &mut self.peers[peer]
+    }
+
+    pub fn report(&self) -> &SimReport {
+        &self.report
+    }
+
+    /// Whether every peer holds the same state.
+    pub fn converged(&self) -> bool {
+        self.peers.windows(2).all(|pair| pair[0] == pair[1])
+    }
+
+    /// Runs rounds until the peers converge or `max_rounds` have been run in total.
+    ///
+    /// Fails if a peer rejects a delta or ends up with a state that doesn't verify, with the
+    /// peer's index at the start of the error's path.
+    pub fn run(&mut self, max_rounds: u64) -> Result<&SimReport, ScaffoldError> {
+        self.report.converged = self.converged();
+        while !self.report.converged && self.report.rounds < max_rounds {
+            self.step()?;
+            self.report.converged = self.converged();
+        }
+        Ok(&self.report)
+    }
+
+    /// Runs the simulation and panics unless the peers converge within `max_rounds`.
+    pub fn assert_converges(&mut self, max_rounds: u64) -> SimReport {
+        let report = match self.run(max_rounds) {
+            Ok(report) => report.clone(),
+            Err(error) => panic!("simulation failed: {}", error),
+        };
+        assert!(
+            report.converged,
+            "peers did not converge within {} rounds",
+            max_rounds
+        );
+        report
+    }
+
+    /// Runs one round: every peer sends its summary to another peer, then the messages due this
+    /// round are delivered in random order.
+    pub fn step(&mut self) -> Result<(), ScaffoldError> {
+        self.report.rounds += 1;
+        let round = self.report.rounds;
+        if self.peers.len() > 1 {
+            for from in 0..self.peers.len() {
+                let offset = self.rng.below(self.peers.len() as u64 - 1) as usize;
+                let to = (from + 1 + offset) % self.peers.len();
+                let summary = self.peers[from].summarize(&self.peers[from], &self.parameters);
+                self.send(from, to, Message::Summary(summary));
+            }
+        }
+
+        let (mut due, in_flight): (Vec<_>, Vec<_>) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|envelope| envelope.deliver_at <= round);
+        self.in_flight = in_flight;
+        self.rng.shuffle(&mut due);
+        for envelope in due {
+            self.deliver(envelope)
+                .map_err(|(peer, e)| e.in_field(format!("peer {}", peer)))?;
+        }
+        Ok(())
+    }
+
+    fn deliver(&mut self, envelope: Envelope<T>) -> Result<(), (usize, ScaffoldError)> {
+        let Envelope {
+            from, to, message, ..
+        } = envelope;
+        let peer = &mut self.peers[to];
+        match message {
+            Message::Summary(summary) => {
+                if let Some(delta) = peer.delta(peer, &self.parameters, &summary) {
+                    self.send(to, from, Message::Delta(delta));
+                }
+            }
+            Message::Delta(delta) => {
+                let parent_state = peer.clone();
+                peer.apply_delta(&parent_state, &self.parameters, &Some(delta))
+                    .and_then(|()| peer.verify(&peer.clone(), &self.parameters))
+                    .map_err(|e| (to, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, from: usize, to: usize, message: Message<T>) {
+        let round = self.report.rounds;
+        self.report.messages_sent += 1;
+        self.report.bytes_sent += match &message {
+            Message::Summary(summary) => bincode::serialized_size(summary),
+            Message::Delta(delta) => bincode::serialized_size(delta),
+        }
+        .expect("summaries and deltas should be serializable");
+
+        let partitioned = self
+            .conditions
+            .partitions
+            .iter()
+            .any(|partition| partition.separates(round, from, to));
+        if partitioned || self.rng.chance(self.conditions.drop_rate) {
+            self.report.messages_dropped += 1;
+            return;
+        }
+        if self.rng.chance(self.conditions.duplicate_rate) {
+            let copy = match &message {
+                Message::Summary(summary) => Message::Summary(summary.clone()),
+                Message::Delta(delta) => Message::Delta(delta.clone()),
+            };
+            self.enqueue(from, to, copy);
+        }
+        self.enqueue(from, to, message);
+    }
+
+    fn enqueue(&mut self, from: usize, to: usize, message: Message<T>) {
+        let delay = 1 + self.rng.below(self.conditions.max_delay.saturating_add(1));
+        self.in_flight.push(Envelope {
+            deliver_at: self.report.rounds.saturating_add(delay),
+            from,
+            to,
+            message,
+        });
+    }
+}
+
+/// The SplitMix64 generator, small and with the same output on every platform.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, slightly biased for large `n`, which doesn't matter here.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        // The top 53 bits give a uniform f64 in [0, 1)
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    fn shuffle<V>(&mut self, values: &mut [V]) {
+        for index in (1..values.len()).rev() {
+            values.swap(index, self.below(index as u64 + 1) as usize);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Property tests for [`ComposableState`] implementations, enabled by the `testing` feature.
+//!
+//! Given a [`Strategy`] that generates valid states, [`check_convergence`] checks that:
+//!
+//! - `merge` is commutative, associative and idempotent,
+//! - applying `delta(summary(a))` to `a` reaches the state the delta was computed from,
+//! - every merged state passes `verify`.
+//!
+//! Failing cases are shrunk and reported by panicking, so the checks can be called from a
+//! `#[test]`:
+//!
+//! ```ignore
+//! use freenet_scaffold::counter::GCounter;
+//! use freenet_scaffold::testing::{check_convergence, proptest::prelude::*};
+//!
+//! #[test]
+//! fn gcounter_converges() {
+//!     let increments = prop::collection::vec((0u8..4, 1u64..10), 0..8);
+//!     check_convergence(
+//!         increments.prop_map(|increments| {
+//!             let mut counter = GCounter::<u8>::new();
+//!             for (actor, n) in increments {
+//!                 counter.increment(actor, n);
+//!             }
+//!             counter
+//!         }),
+//!         &(),
+//!         &(),
+//!     );
+//! }
+//! ```
+
+use crate::ComposableState;
+pub use proptest;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestCaseError, TestRunner};
+use std::fmt::Debug;
+
+/// Checks the convergence properties of a state whose parent state doesn't depend on it, such as
+/// a built-in type used as a field. Every state is passed `parent_state`.
+///
+/// Panics with the smallest failing case found.
+pub fn check_convergence<T>(
+    states: impl Strategy<Value = T>,
+    parent_state: &T::ParentState,
+    parameters: &T::Parameters,
+) where
+    T: ComposableState + Clone + PartialEq + Debug,
+{
+    check(states, parameters, |_| parent_state.clone());
+}
+
+/// Checks the convergence properties of a state that is its own parent state, such as a
+/// `#[composable]` struct.
+///
+/// Panics with the smallest failing case found.
+pub fn check_root_convergence<T>(states: impl Strategy<Value = T>, parameters: &T::Parameters)
+where
+    T: ComposableState<ParentState = T> + Clone + PartialEq + Debug,
+{
+    check(states, parameters, T::clone);
+}
+
+fn check<T, F>(states: impl Strategy<Value = T>, parameters: &T::Parameters, parent: F)
+where
+    T: ComposableState + Clone + PartialEq + Debug,
+    F: Fn(&T) -> T::ParentState,
+{
+    // Failing cases are reported rather than persisted, the runner can't tell which crate's
+    // source tree they'd belong in
+    let mut runner = TestRunner::new(Config {
+        failure_persistence: None,
+        ..Config::default()
+    });
+    let result = runner.run(&vec(states, 3), |states| {
+        let (a, b, c) = (&states[0], &states[1], &states[2]);
+        for state in [a, b, c] {
+            state
+                .verify(&parent(state), parameters)
+                .map_err(|e| TestCaseError::fail(format!("generated state is invalid: {}", e)))?;
+        }
+
+        let ab = merge(a, b, parameters, &parent)?;
+        let ba = merge(b, a, parameters, &parent)?;
+        prop_assert_eq!(&ab, &ba, "merge is not commutative");
+
+        let ab_c = merge(&ab, c, parameters, &parent)?;
+        let a_bc = merge(a, &merge(b, c, parameters, &parent)?, parameters, &parent)?;
+        prop_assert_eq!(&ab_c, &a_bc, "merge is not associative");
+
+        let aa = merge(a, a, parameters, &parent)?;
+        prop_assert_eq!(&aa, a, "merge is not idempotent");
+
+        // `ab` has seen everything `a` has, so its delta should bring `a` up to date
+        let delta = ab.delta(
+            &parent(&ab),
+            parameters,
+            &a.summarize(&parent(a), parameters),
+        );
+        let mut updated = a.clone();
+        updated
+            .apply_delta(&parent(a), parameters, &delta)
+            .map_err(|e| TestCaseError::fail(format!("applying a delta failed: {}", e)))?;
+        prop_assert_eq!(
+            &updated,
+            &ab,
+            "applying a delta doesn't reach the state it was computed from"
+        );
+        Ok(())
+    });
+    if let Err(error) = result {
+        panic!("{}", error);
+    }
+}
+
+/// `a` merged with `b`, which must pass `verify`.
+fn merge<T, F>(a: &T, b: &T, parameters: &T::Parameters, parent: &F) -> Result<T, TestCaseError>
+where
+    T: ComposableState + Clone,
+    F: Fn(&T) -> T::ParentState,
+{
+    let mut merged = a.clone();
+    merged
+        .merge(&parent(a), parameters, b)
+        .map_err(|e| TestCaseError::fail(format!("merge failed: {}", e)))?;
+    merged
+        .verify(&parent(&merged), parameters)
+        .map_err(|e| TestCaseError::fail(format!("merged state is invalid: {}", e)))?;
+    Ok(merged)
+}
@@ -1,6 +1,16 @@
 use super::*;
 use crate as freenet_scaffold;
+use crate::append_log::{AppendLog, RetentionPolicy};
+use crate::counter::{GCounter, PnCounter};
+use crate::map::{ComposableMap, ComposableMapDelta};
+use crate::register::{LwwRegister, LwwRegisterDelta, MvRegister};
+use crate::sequence::Rga;
+use crate::set::{GSet, OrSet, TwoPhaseSet, TwoPhaseSetDelta};
+use crate::util::FastHash;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct ContractualI32(pub i32);
@@ -21,7 +31,7 @@ impl ComposableState for ContractualI32 {
         &self,
         _parent_state: &Self::ParentState,
         _parameters: &Self::Parameters,
-    ) -> Result<(), String> {
+    ) -> Result<(), ScaffoldError> {
         Ok(())
     }
 
@@ -47,7 +57,7 @@ impl ComposableState for ContractualI32 {
         _parent_state: &Self::ParentState,
         _parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String> {
+    ) -> Result<(), ScaffoldError> {
         match delta {
             Some(delta) => {
                 self.0 += *delta;
@@ -68,7 +78,7 @@ impl ComposableState for ContractualString {
         &self,
         _parent_state: &Self::ParentState,
         _parameters: &Self::Parameters,
-    ) -> Result<(), String> {
+    ) -> Result<(), ScaffoldError> {
         Ok(())
     }
 
@@ -98,7 +108,7 @@ impl ComposableState for ContractualString {
         _parent_state: &Self::ParentState,
         _parameters: &Self::Parameters,
         delta: &Option<Self::Delta>,
-    ) -> Result<(), String> {
+    ) -> Result<(), ScaffoldError> {
         if let Some(delta) = delta {
             self.0 = delta.clone()
         }
@@ -147,3 +157,1669 @@ fn test_contractual_macro() {
         .is_ok());
     assert_eq!(test_struct, new_state);
 }
+
+/// A number that fails `verify` when negative.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct NonNegative(pub i32);
+
+/// Text that fails `verify` when longer than 64 bytes, implemented against the legacy trait.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ShortText(pub String);
+
+impl ComposableState for NonNegative {
+    type ParentState = Checked;
+    type Summary = i32;
+    type Delta = i32;
+    type Parameters = TestStructParameters;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        if self.0 < 0 {
+            return Err(ScaffoldError::verification_failed(
+                "number must not be negative",
+            ));
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.0
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        (self.0 != *old_state_summary).then_some(self.0)
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        if let Some(delta) = delta {
+            self.0 = *delta;
+        }
+        Ok(())
+    }
+}
+
+impl LegacyComposableState for ShortText {
+    type ParentState = Checked;
+    type Summary = String;
+    type Delta = String;
+    type Parameters = TestStructParameters;
+
+    fn verify(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), String> {
+        if self.0.len() > 64 {
+            return Err("text must not exceed 64 bytes".to_string());
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.0.clone()
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        (self.0 != *old_state_summary).then(|| self.0.clone())
+    }
+
+    fn apply_delta(
+        &mut self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), String> {
+        if let Some(delta) = delta {
+            self.0 = delta.clone();
+        }
+        Ok(())
+    }
+}
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Checked {
+    number: NonNegative,
+    text: ShortText,
+}
+
+impl Checked {
+    fn new(number: i32, text: &str) -> Self {
+        Checked {
+            number: NonNegative(number),
+            text: ShortText(text.to_string()),
+        }
+    }
+}
+
+#[test]
+fn test_error_path() {
+    let parameters = TestStructParameters {};
+
+    let negative = Checked::new(-1, "hello");
+    let err = negative.verify(&negative, &parameters).unwrap_err();
+    assert_eq!(
+        err,
+        ScaffoldError::VerificationFailed {
+            path: vec!["number".to_string()],
+            reason: "number must not be negative".to_string(),
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "verification failed at `number`: number must not be negative"
+    );
+
+    // String errors from legacy implementations surface as Custom
+    let long = Checked::new(1, &"x".repeat(65));
+    let err = long.verify(&long, &parameters).unwrap_err();
+    assert!(matches!(err, ScaffoldError::Custom { .. }));
+    assert_eq!(err.path(), ["text".to_string()]);
+}
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Tally {
+    votes: GCounter<String, Tally, TestStructParameters>,
+}
+
+#[test]
+fn test_gcounter_concurrent_merge() {
+    let parameters = TestStructParameters {};
+    let mut alice = Tally {
+        votes: GCounter::new(),
+    };
+    alice.votes.increment("alice".to_string(), 2);
+    let mut bob = alice.clone();
+    alice.votes.increment("alice".to_string(), 1);
+    bob.votes.increment("bob".to_string(), 5);
+
+    // Only entries that grew are sent
+    let delta = alice
+        .delta(&alice, &parameters, &bob.summarize(&bob, &parameters))
+        .unwrap();
+    assert_eq!(delta.votes.unwrap().len(), 1);
+
+    let mut left = alice.clone();
+    left.merge(&alice, &parameters, &bob).unwrap();
+    let mut right = bob.clone();
+    right.merge(&bob, &parameters, &alice).unwrap();
+    assert_eq!(left, right);
+    assert_eq!(left.votes.value(), 8);
+
+    // Merging the same state again changes nothing
+    let before = left.clone();
+    left.merge(&before, &parameters, &bob).unwrap();
+    assert_eq!(left, before);
+}
+
+#[test]
+fn test_pncounter_converges_in_any_order() {
+    let mut base: PnCounter<String> = PnCounter::new();
+    base.increment("alice".to_string(), 10);
+
+    let mut alice = base.clone();
+    alice.decrement("alice".to_string(), 3);
+    let mut bob = base.clone();
+    bob.increment("bob".to_string(), 4);
+    bob.decrement("bob".to_string(), 20);
+
+    let from_alice = alice.delta(&(), &(), &base.summarize(&(), &()));
+    let from_bob = bob.delta(&(), &(), &base.summarize(&(), &()));
+
+    let mut first = base.clone();
+    first.apply_delta(&(), &(), &from_alice).unwrap();
+    first.apply_delta(&(), &(), &from_bob).unwrap();
+    let mut second = base.clone();
+    second.apply_delta(&(), &(), &from_bob).unwrap();
+    second.apply_delta(&(), &(), &from_alice).unwrap();
+    // A duplicated delta has no further effect
+    second.apply_delta(&(), &(), &from_alice).unwrap();
+
+    assert_eq!(first, second);
+    assert_eq!(first.value(), 10 - 3 + 4 - 20);
+}
+
+#[test]
+fn test_lww_register_concurrent_writes() {
+    let mut alice: LwwRegister<String> = LwwRegister::new("initial".to_string(), 1);
+    let mut bob = alice.clone();
+    assert!(alice.set("from alice".to_string(), 5));
+    assert!(bob.set("from bob".to_string(), 5));
+    assert!(!bob.set("too old".to_string(), 4));
+
+    // Each side sends its value only if it wins against the other's summary
+    let to_bob = alice.delta(&(), &(), &bob.summarize(&(), &()));
+    let to_alice = bob.delta(&(), &(), &alice.summarize(&(), &()));
+    assert!(to_bob.is_some() != to_alice.is_some());
+
+    bob.apply_delta(&(), &(), &to_bob).unwrap();
+    alice.apply_delta(&(), &(), &to_alice).unwrap();
+    assert_eq!(alice, bob);
+
+    // Applying the losing delta afterwards doesn't flip the value back
+    let loser = if alice.get() == "from alice" {
+        "from bob"
+    } else {
+        "from alice"
+    };
+    let winner = alice.clone();
+    let losing = LwwRegisterDelta {
+        value: loser.to_string(),
+        timestamp: 5,
+    };
+    alice.apply_delta(&(), &(), &Some(losing)).unwrap();
+    assert_eq!(alice, winner);
+}
+
+#[test]
+fn test_mv_register_surfaces_conflicts() {
+    let mut alice: MvRegister<String, String> = MvRegister::new();
+    alice.set("alice".to_string(), "draft".to_string()).unwrap();
+    let mut bob = alice.clone();
+
+    alice
+        .set("alice".to_string(), "from alice".to_string())
+        .unwrap();
+    bob.set("bob".to_string(), "from bob".to_string()).unwrap();
+
+    let mut merged = alice.clone();
+    merged.merge(&(), &(), &bob).unwrap();
+    assert!(merged.is_conflicted());
+    assert!(merged.verify(&(), &()).is_ok());
+    let mut values: Vec<_> = merged.values().cloned().collect();
+    values.sort();
+    assert_eq!(values, ["from alice", "from bob"]);
+
+    let mut other = bob.clone();
+    other.merge(&(), &(), &alice).unwrap();
+    assert_eq!(merged, other);
+
+    // A write after seeing both values resolves the conflict everywhere
+    merged
+        .set("bob".to_string(), "resolved".to_string())
+        .unwrap();
+    alice
+        .apply_delta(
+            &(),
+            &(),
+            &merged.delta(&(), &(), &alice.summarize(&(), &())),
+        )
+        .unwrap();
+    assert!(!alice.is_conflicted());
+    assert_eq!(alice.values().collect::<Vec<_>>(), ["resolved"]);
+}
+
+#[test]
+fn test_version_vector_normalizes_untrusted_counters() {
+    use crate::version_vector::VersionVector;
+
+    let mut counters = BTreeMap::new();
+    counters.insert("alice".to_string(), 0u64);
+    counters.insert("bob".to_string(), u64::MAX);
+    let bytes = bincode::serialize(&counters).unwrap();
+    let mut version: VersionVector<String> = bincode::deserialize(&bytes).unwrap();
+
+    let mut expected = VersionVector::new();
+    expected.observe("bob".to_string(), u64::MAX);
+    assert_eq!(version, expected);
+    assert!(version.is_exhausted());
+    assert_eq!(
+        version.increment("bob".to_string()).unwrap_err().reason(),
+        "a version vector counter has reached u64::MAX"
+    );
+    assert_eq!(version, expected);
+}
+
+#[test]
+fn test_mv_register_rejects_exhausted_counters() {
+    use crate::version_vector::VersionVector;
+
+    let mut exhausted = VersionVector::new();
+    exhausted.observe("mallory".to_string(), u64::MAX);
+
+    let mut alice: MvRegister<String, String> = MvRegister::new();
+    alice.set("alice".to_string(), "draft".to_string()).unwrap();
+    let before = alice.clone();
+    let delta = Some(vec![(exhausted.clone(), "stuck".to_string())]);
+    assert!(alice.apply_delta(&(), &(), &delta).is_err());
+    assert_eq!(alice, before);
+
+    // A register that already holds such a counter fails to verify, and can't be written again
+    let bytes = bincode::serialize(&vec![(exhausted, "stuck".to_string())]).unwrap();
+    let stuck: MvRegister<String, String> = bincode::deserialize(&bytes).unwrap();
+    assert!(stuck.verify(&(), &()).is_err());
+    let mut stuck = stuck;
+    assert!(stuck
+        .set("mallory".to_string(), "again".to_string())
+        .is_err());
+}
+
+#[test]
+fn test_or_set_add_wins_over_concurrent_remove() {
+    let mut alice: OrSet<String> = OrSet::new();
+    alice.add("rust".to_string(), 1);
+    alice.add("freenet".to_string(), 2);
+    let mut bob = alice.clone();
+
+    // Alice removes "rust" while Bob concurrently re-adds it
+    assert!(alice.remove(&"rust".to_string()));
+    bob.add("rust".to_string(), 3);
+
+    let to_bob = alice.delta(&(), &(), &bob.summarize(&(), &())).unwrap();
+    assert!(to_bob.additions.is_empty());
+    assert_eq!(to_bob.tombstones.len(), 1);
+
+    let mut left = alice.clone();
+    left.merge(&(), &(), &bob).unwrap();
+    let mut right = bob.clone();
+    right.merge(&(), &(), &alice).unwrap();
+    assert_eq!(left, right);
+    assert!(left.contains(&"rust".to_string()));
+    assert_eq!(left.len(), 2);
+    assert!(left.verify(&(), &()).is_ok());
+}
+
+#[test]
+fn test_or_set_rejects_malformed_tombstones() {
+    let mut set: OrSet<String> = OrSet::new();
+    set.add("rust".to_string(), 1);
+    let mut state = bincode::serialize(&set).unwrap();
+
+    // Re-encode the set with the live addition's id also listed as a tombstone
+    let (elements, _): (BTreeMap<FastHash, (u64, String)>, BTreeSet<FastHash>) =
+        bincode::deserialize(&state).unwrap();
+    let tombstones: BTreeSet<FastHash> = elements.keys().copied().collect();
+    state = bincode::serialize(&(elements, tombstones)).unwrap();
+    let malformed: OrSet<String> = bincode::deserialize(&state).unwrap();
+
+    assert!(matches!(
+        malformed.verify(&(), &()),
+        Err(ScaffoldError::VerificationFailed { .. })
+    ));
+}
+
+#[test]
+fn test_gset_sends_missing_elements() {
+    let mut alice: GSet<String> = GSet::new();
+    alice.insert("mallory".to_string());
+    let mut bob = alice.clone();
+    alice.insert("eve".to_string());
+    bob.insert("trudy".to_string());
+
+    let to_bob = alice.delta(&(), &(), &bob.summarize(&(), &()));
+    assert_eq!(to_bob, Some(vec!["eve".to_string()]));
+
+    let mut left = alice.clone();
+    left.merge(&(), &(), &bob).unwrap();
+    let mut right = bob.clone();
+    right.merge(&(), &(), &alice).unwrap();
+    assert_eq!(left, right);
+    assert_eq!(left.len(), 3);
+}
+
+#[test]
+fn test_two_phase_set_never_re_adds() {
+    let mut alice: TwoPhaseSet<String> = TwoPhaseSet::new();
+    alice.insert("topic".to_string());
+    let mut bob = alice.clone();
+
+    assert!(alice.remove(&"topic".to_string()));
+    assert!(!alice.insert("topic".to_string()));
+    bob.insert("other".to_string());
+
+    bob.merge(&(), &(), &alice).unwrap();
+    assert!(!bob.contains(&"topic".to_string()));
+    assert!(bob.is_removed(&"topic".to_string()));
+
+    // A stale peer re-sending the element doesn't bring it back
+    let stale: TwoPhaseSetDelta<String> = TwoPhaseSetDelta {
+        elements: vec!["topic".to_string()],
+        removed: vec![],
+    };
+    bob.apply_delta(&(), &(), &Some(stale)).unwrap();
+    assert!(!bob.contains(&"topic".to_string()));
+    assert!(bob.verify(&(), &()).is_ok());
+
+    alice.merge(&(), &(), &bob).unwrap();
+    assert_eq!(alice, bob);
+}
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Directory {
+    rooms: ComposableMap<String, GCounter<String, Directory, TestStructParameters>>,
+}
+
+#[test]
+fn test_composable_map_nested_deltas() {
+    let parameters = TestStructParameters {};
+    let mut alice = Directory {
+        rooms: ComposableMap::new(),
+    };
+    alice.rooms.insert("lobby".to_string(), GCounter::new());
+    let mut bob = alice.clone();
+
+    alice
+        .rooms
+        .get_mut(&"lobby".to_string())
+        .unwrap()
+        .increment("alice".to_string(), 1);
+    bob.rooms.insert("garden".to_string(), GCounter::new());
+
+    // Existing entries travel as nested deltas, new ones whole
+    let to_bob = alice
+        .delta(&alice, &parameters, &bob.summarize(&bob, &parameters))
+        .unwrap()
+        .rooms
+        .unwrap();
+    assert!(to_bob.added.is_empty());
+    assert_eq!(to_bob.updated.len(), 1);
+    let to_alice = bob
+        .delta(&bob, &parameters, &alice.summarize(&alice, &parameters))
+        .unwrap()
+        .rooms
+        .unwrap();
+    assert_eq!(to_alice.added.len(), 1);
+    assert!(to_alice.updated.is_empty());
+
+    let mut left = alice.clone();
+    left.merge(&alice, &parameters, &bob).unwrap();
+    let mut right = bob.clone();
+    right.merge(&bob, &parameters, &alice).unwrap();
+    assert_eq!(left, right);
+    assert_eq!(left.rooms.len(), 2);
+
+    let bad = DirectoryDelta {
+        rooms: Some(ComposableMapDelta {
+            added: BTreeMap::new(),
+            updated: [("cellar".to_string(), BTreeMap::new())].into(),
+        }),
+    };
+    let err = left
+        .apply_delta(&right, &parameters, &Some(bad))
+        .unwrap_err();
+    assert!(matches!(err, ScaffoldError::InvalidDelta { .. }));
+    assert_eq!(err.path(), ["rooms", "cellar"]);
+}
+
+#[test]
+fn test_composable_map_keyed_by_id() {
+    let id = crate::util::fast_hash(b"lobby");
+    let mut rooms: ComposableMap<FastHash, GCounter<String>> = ComposableMap::new();
+    rooms.insert(id, GCounter::new());
+    assert!(rooms.verify(&(), &()).is_ok());
+
+    let bad = ComposableMapDelta {
+        added: BTreeMap::new(),
+        updated: [(crate::util::fast_hash(b"cellar"), BTreeMap::new())].into(),
+    };
+    let err = rooms.apply_delta(&(), &(), &Some(bad)).unwrap_err();
+    assert_eq!(
+        err.path(),
+        [format!(
+            "{:016x}",
+            crate::util::fast_hash(b"cellar").0 as u64
+        )]
+    );
+}
+
+#[test]
+fn test_rga_concurrent_edits() {
+    let mut alice: Rga<char, String> = Rga::new();
+    for c in "helo".chars() {
+        alice.push("alice".to_string(), c);
+    }
+    let mut bob = alice.clone();
+
+    alice.insert("alice".to_string(), 3, 'l');
+    alice.push("alice".to_string(), '!');
+    bob.delete("bob".to_string(), 0);
+    bob.insert("bob".to_string(), 0, 'H');
+    bob.push("bob".to_string(), '?');
+
+    // Only the operations the other side hasn't seen are sent
+    let to_bob = alice.delta(&(), &(), &bob.summarize(&(), &())).unwrap();
+    assert_eq!(to_bob.len(), 2);
+
+    let mut left = alice.clone();
+    left.merge(&(), &(), &bob).unwrap();
+    let mut right = bob.clone();
+    right.merge(&(), &(), &alice).unwrap();
+    assert_eq!(left, right);
+    assert!(left.verify(&(), &()).is_ok());
+    let text = left.to_string();
+    assert!(text == "Hello!?" || text == "Hello?!", "{}", text);
+
+    // Duplicated deltas are ignored
+    left.apply_delta(&(), &(), &Some(to_bob)).unwrap();
+    assert_eq!(left, right);
+}
+
+#[test]
+fn test_rga_edits_after_reload() {
+    let mut alice: Rga<char, String> = Rga::new();
+    for c in "held".chars() {
+        alice.push("alice".to_string(), c);
+    }
+    alice.delete("alice".to_string(), 3);
+    let bytes = bincode::serialize(&alice).unwrap();
+    let mut reloaded: Rga<char, String> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(reloaded, alice);
+
+    // Elements are found by id in the reloaded sequence as in the original
+    alice.insert("alice".to_string(), 2, 'l');
+    alice.push("alice".to_string(), 'o');
+    let delta = alice.delta(&(), &(), &reloaded.summarize(&(), &()));
+    reloaded.apply_delta(&(), &(), &delta).unwrap();
+    assert_eq!(reloaded.to_string(), "hello");
+    assert_eq!(reloaded, alice);
+    assert!(reloaded.verify(&(), &()).is_ok());
+}
+
+#[test]
+fn test_append_log_prunes_identically() {
+    let policy = RetentionPolicy {
+        max_entries: Some(3),
+        max_bytes: None,
+        max_age: Some(100),
+    };
+    let mut alice: AppendLog<String, (), RetentionPolicy> = AppendLog::new();
+    let mut bob: AppendLog<String, (), RetentionPolicy> = AppendLog::new();
+    alice.push(&policy, 10, "a1".to_string());
+    alice.push(&policy, 30, "a2".to_string());
+    bob.push(&policy, 20, "b1".to_string());
+    bob.push(&policy, 30, "b2".to_string());
+    bob.push(&policy, 40, "b3".to_string());
+
+    // Each side receives a different set of entries but prunes to the same result
+    let mut left = alice.clone();
+    left.merge(&(), &policy, &bob).unwrap();
+    let mut right = bob.clone();
+    right.merge(&(), &policy, &alice).unwrap();
+    assert_eq!(left, right);
+    let timestamps: Vec<u64> = left.iter().map(|entry| entry.timestamp()).collect();
+    assert_eq!(timestamps, [30, 30, 40]);
+    assert!(left.verify(&(), &policy).is_ok());
+
+    // The policy comes from the parameters, a log kept under a laxer one doesn't verify
+    let mut unbounded: AppendLog<String, (), RetentionPolicy> = AppendLog::new();
+    for timestamp in 0..5 {
+        unbounded.push(&RetentionPolicy::default(), timestamp, "spam".to_string());
+    }
+    assert!(unbounded.verify(&(), &policy).is_err());
+}
+
+/// Parameters of a chat log whose entries can't be timestamped after `latest`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ChatLogParameters {
+    policy: RetentionPolicy,
+    latest: u64,
+}
+
+impl crate::append_log::LogParameters<()> for ChatLogParameters {
+    fn retention_policy(&self) -> &RetentionPolicy {
+        &self.policy
+    }
+
+    fn accepts_timestamp(&self, _parent_state: &(), timestamp: u64) -> bool {
+        timestamp <= self.latest
+    }
+}
+
+#[test]
+fn test_append_log_rejects_future_timestamps() {
+    let parameters = ChatLogParameters {
+        policy: RetentionPolicy {
+            max_age: Some(100),
+            ..RetentionPolicy::default()
+        },
+        latest: 1000,
+    };
+    let mut log: AppendLog<String, (), ChatLogParameters> = AppendLog::new();
+    log.push(&parameters, 10, "hello".to_string());
+    log.push(&parameters, 20, "world".to_string());
+
+    // An entry far in the future would age everything else out, so it is refused
+    let wipe = Some(vec![(u64::MAX, "wipe".to_string())]);
+    let error = log.apply_delta(&(), &parameters, &wipe).unwrap_err();
+    assert_eq!(
+        error.reason(),
+        format!("entry has an unacceptable timestamp {}", u64::MAX)
+    );
+    assert_eq!(log.len(), 2);
+
+    let mut forged: AppendLog<String, (), ChatLogParameters> = AppendLog::new();
+    forged.push(&parameters, u64::MAX, "wipe".to_string());
+    assert!(forged.verify(&(), &parameters).is_err());
+}
+
+#[test]
+fn test_append_log_max_bytes() {
+    let policy = RetentionPolicy {
+        max_bytes: Some(60),
+        ..RetentionPolicy::default()
+    };
+    let mut log: AppendLog<String, (), RetentionPolicy> = AppendLog::new();
+    for timestamp in 0..10 {
+        log.push(&policy, timestamp, "0123456789".to_string());
+    }
+    // Each entry is 8 bytes of timestamp, 8 of string length and 10 of string
+    assert_eq!(log.len(), 2);
+    assert_eq!(log.iter().next().unwrap().timestamp(), 8);
+}
+
+type PostText = LwwRegister<String, u64, Post, TestStructParameters>;
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub enum Post {
+    Draft(PostText),
+    Published(PostText),
+    Archived,
+}
+
+#[test]
+fn test_composable_enum() {
+    let parameters = TestStructParameters {};
+    let mut alice = Post::Draft(LwwRegister::new("first".to_string(), 1));
+    let mut bob = alice.clone();
+
+    // Within the same variant, changes travel as the variant's own delta
+    if let Post::Draft(text) = &mut alice {
+        text.set("second".to_string(), 2);
+    }
+    let delta = alice.delta(&alice, &parameters, &bob.summarize(&bob, &parameters));
+    assert!(matches!(delta, Some(PostDelta::Draft(_))));
+    bob.apply_delta(&bob.clone(), &parameters, &delta).unwrap();
+    assert_eq!(alice, bob);
+
+    // Concurrently publishing and archiving converges on the later variant
+    alice = Post::Published(LwwRegister::new("second".to_string(), 3));
+    bob = Post::Archived;
+    let delta = alice.delta(&alice, &parameters, &bob.summarize(&bob, &parameters));
+    assert!(delta.is_none());
+    let delta = bob.delta(&bob, &parameters, &alice.summarize(&alice, &parameters));
+    assert_eq!(delta, Some(PostDelta::Replace(Post::Archived)));
+
+    let mut left = alice.clone();
+    left.merge(&alice, &parameters, &bob).unwrap();
+    let mut right = bob.clone();
+    right.merge(&bob, &parameters, &alice).unwrap();
+    assert_eq!(left, Post::Archived);
+    assert_eq!(right, Post::Archived);
+
+    // A delta for a variant the state has moved past is stale
+    let stale = PostDelta::Published(LwwRegisterDelta {
+        value: "third".to_string(),
+        timestamp: 4,
+    });
+    left.apply_delta(&right, &parameters, &Some(stale.clone()))
+        .unwrap();
+    assert_eq!(left, Post::Archived);
+    let mut draft = Post::Draft(LwwRegister::new("first".to_string(), 1));
+    let err = draft
+        .apply_delta(&right, &parameters, &Some(stale))
+        .unwrap_err();
+    assert_eq!(err.path(), ["Published"]);
+}
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Pair(
+    GCounter<String, Pair, TestStructParameters>,
+    GSet<String, Pair, TestStructParameters>,
+);
+
+#[test]
+fn test_composable_tuple_struct() {
+    let parameters = TestStructParameters {};
+    let mut alice = Pair(GCounter::new(), GSet::new());
+    let mut bob = alice.clone();
+    alice.0.increment("alice".to_string(), 1);
+    bob.1.insert("bob".to_string());
+
+    let delta = alice
+        .delta(&alice, &parameters, &bob.summarize(&bob, &parameters))
+        .unwrap();
+    assert!(delta.0.is_some());
+    assert!(delta.1.is_none());
+
+    let mut left = alice.clone();
+    left.merge(&alice, &parameters, &bob).unwrap();
+    let mut right = bob.clone();
+    right.merge(&bob, &parameters, &alice).unwrap();
+    assert_eq!(left, right);
+}
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Room<K: Ord + Clone + Debug + Serialize + DeserializeOwned> {
+    members: GSet<K, Room<K>, TestStructParameters>,
+    votes: GCounter<K, Room<K>, TestStructParameters>,
+}
+
+#[test]
+fn test_composable_generic_struct() {
+    let parameters = TestStructParameters {};
+    let mut alice: Room<u32> = Room {
+        members: GSet::new(),
+        votes: GCounter::new(),
+    };
+    let mut bob = alice.clone();
+    alice.members.insert(1);
+    bob.votes.increment(2, 3);
+
+    // The generated types are generic too
+    let summary: RoomSummary<u32> = bob.summarize(&bob, &parameters);
+    let delta: Option<RoomDelta<u32>> = alice.delta(&alice, &parameters, &summary);
+    bob.apply_delta(&bob.clone(), &parameters, &delta).unwrap();
+    alice.merge(&alice.clone(), &parameters, &bob).unwrap();
+    assert_eq!(alice, bob);
+    assert!(alice.members.contains(&1));
+    assert_eq!(alice.votes.value(), 3);
+}
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Roster {
+    #[composable(rename = "people")]
+    members: GSet<String, Roster, TestStructParameters>,
+    #[composable(derived = "Roster::count_members")]
+    member_count: usize,
+    /// Rebuilt locally, never sent to other peers
+    #[composable(skip)]
+    #[serde(skip)]
+    lookups: u32,
+}
+
+impl Roster {
+    fn count_members(&self) -> usize {
+        self.members.len()
+    }
+}
+
+#[test]
+fn test_composable_field_attributes() {
+    let parameters = TestStructParameters {};
+    let mut alice = Roster {
+        members: GSet::new(),
+        member_count: 0,
+        lookups: 0,
+    };
+    let mut bob = alice.clone();
+    bob.lookups = 7;
+    alice.members.insert("carol".to_string());
+    alice.members.insert("dave".to_string());
+
+    // Only the synced field appears in the summary and delta, under its new name
+    let summary = bob.summarize(&bob, &parameters);
+    assert!(summary.people.is_empty());
+    let delta = alice.delta(&alice, &parameters, &summary).unwrap();
+    assert_eq!(delta.people.as_ref().map(Vec::len), Some(2));
+
+    bob.apply_delta(&bob.clone(), &parameters, &Some(delta))
+        .unwrap();
+    assert_eq!(bob.member_count, 2);
+    assert_eq!(bob.lookups, 7);
+}
+
+/// Total spent from a [`Budget`], which must stay within the budget's cap.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Spent(u64);
+
+impl ComposableState for Spent {
+    type ParentState = Budget;
+    type Summary = u64;
+    type Delta = u64;
+    type Parameters = TestStructParameters;
+
+    fn verify(
+        &self,
+        parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Result<(), ScaffoldError> {
+        if self.0 > *parent_state.cap.get() {
+            return Err(ScaffoldError::limit_exceeded("spent more than the cap"));
+        }
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+    ) -> Self::Summary {
+        self.0
+    }
+
+    fn delta(
+        &self,
+        _parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        old_state_summary: &Self::Summary,
+    ) -> Option<Self::Delta> {
+        (self.0 > *old_state_summary).then_some(self.0)
+    }
+
+    fn apply_delta(
+        &mut self,
+        parent_state: &Self::ParentState,
+        _parameters: &Self::Parameters,
+        delta: &Option<Self::Delta>,
+    ) -> Result<(), ScaffoldError> {
+        if let Some(spent) = delta {
+            if *spent > *parent_state.cap.get() {
+                return Err(ScaffoldError::limit_exceeded("spent more than the cap"));
+            }
+            self.0 = self.0.max(*spent);
+        }
+        Ok(())
+    }
+}
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Budget {
+    // Declared first for readability, but checked against the updated cap
+    #[composable(after = "cap")]
+    spent: Spent,
+    cap: LwwRegister<u64, u64, Budget, TestStructParameters>,
+}
+
+#[test]
+fn test_composable_field_dependencies() {
+    let parameters = TestStructParameters {};
+    let mut alice = Budget {
+        spent: Spent(0),
+        cap: LwwRegister::new(5, 0),
+    };
+    let mut bob = alice.clone();
+    alice.cap.set(10, 1);
+    alice.spent = Spent(8);
+    alice.verify(&alice, &parameters).unwrap();
+
+    // The cap is raised before the spending is checked against it
+    let delta = alice.delta(&alice, &parameters, &bob.summarize(&bob, &parameters));
+    bob.apply_delta(&bob.clone(), &parameters, &delta).unwrap();
+    assert_eq!(bob, alice);
+}
+
+#[composable(
+    summary_derive(Eq, Hash),
+    delta_derive(Eq, Hash),
+    delta_vis = "pub(crate)",
+    summary_name = "ScoreboardVersion"
+)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub(crate) struct Scoreboard {
+    players: GSet<String, Scoreboard, TestStructParameters>,
+    points: GCounter<String, Scoreboard, TestStructParameters>,
+}
+
+#[test]
+fn test_composable_generated_type_options() {
+    let parameters = TestStructParameters {};
+    let mut scoreboard = Scoreboard {
+        players: GSet::new(),
+        points: GCounter::new(),
+    };
+    let empty = scoreboard.summarize(&scoreboard, &parameters);
+    scoreboard.players.insert("alice".to_string());
+    scoreboard.points.increment("alice".to_string(), 2);
+
+    // Summaries and deltas can be hashed
+    let summaries: std::collections::HashSet<ScoreboardVersion> = [
+        empty.clone(),
+        scoreboard.summarize(&scoreboard, &parameters),
+        empty.clone(),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(summaries.len(), 2);
+
+    let mut deltas: std::collections::HashMap<ScoreboardDelta, u32> =
+        std::collections::HashMap::new();
+    let delta = scoreboard.delta(&scoreboard, &parameters, &empty).unwrap();
+    *deltas.entry(delta.clone()).or_default() += 1;
+    *deltas.entry(delta).or_default() += 1;
+    assert_eq!(deltas.len(), 1);
+}
+
+#[test]
+fn test_compose_deltas() {
+    let parameters = TestStructParameters {};
+    let original = Scoreboard {
+        players: GSet::new(),
+        points: GCounter::new(),
+    };
+    let mut updated = original.clone();
+    updated.players.insert("alice".to_string());
+    updated.points.increment("alice".to_string(), 2);
+    let first = updated
+        .delta(
+            &updated,
+            &parameters,
+            &original.summarize(&original, &parameters),
+        )
+        .unwrap();
+    let intermediate = updated.clone();
+    updated.points.increment("alice".to_string(), 1);
+    updated.points.increment("bob".to_string(), 4);
+    let second = updated
+        .delta(
+            &updated,
+            &parameters,
+            &intermediate.summarize(&intermediate, &parameters),
+        )
+        .unwrap();
+
+    // A relay forwards both updates as one delta
+    let composed = Scoreboard::compose_deltas(&first, &second).unwrap();
+    let mut receiver = original.clone();
+    receiver
+        .apply_delta(&original, &parameters, &Some(composed))
+        .unwrap();
+    assert_eq!(receiver, updated);
+
+    // An entry added by the first delta can't be updated by the second without its state
+    type Counters = ComposableMap<String, GCounter<String>>;
+    let mut added = ComposableMapDelta::<String, GCounter<String>> {
+        added: BTreeMap::new(),
+        updated: BTreeMap::new(),
+    };
+    added.added.insert("votes".to_string(), GCounter::new());
+    let mut update = added.clone();
+    update.added.clear();
+    update.updated.insert(
+        "votes".to_string(),
+        BTreeMap::from([("carol".to_string(), 1)]),
+    );
+    assert!(Counters::compose_deltas(&added, &update).is_none());
+    assert!(Counters::compose_deltas(&update, &update).is_some());
+}
+
+#[composable(invertible)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Document {
+    tags: OrSet<String, Document, TestStructParameters>,
+    likes: PnCounter<String, Document, TestStructParameters>,
+}
+
+#[test]
+fn test_invert_delta_undoes_edit() {
+    let parameters = TestStructParameters {};
+    let mut before = Document {
+        tags: OrSet::new(),
+        likes: PnCounter::new(),
+    };
+    before.tags.add("draft".to_string(), 1);
+    before.likes.increment("alice".to_string(), 2);
+
+    let mut after = before.clone();
+    after.tags.remove(&"draft".to_string());
+    after.tags.add("final".to_string(), 2);
+    after.likes.increment("bob".to_string(), 3);
+    after.likes.decrement("alice".to_string(), 1);
+    let delta = after
+        .delta(&after, &parameters, &before.summarize(&before, &parameters))
+        .unwrap();
+
+    // Undo is a new delta that goes on top of the edit
+    let inverse = before.invert_delta(&before, &parameters, &delta).unwrap();
+    let mut undone = after.clone();
+    undone
+        .apply_delta(&after, &parameters, &Some(inverse))
+        .unwrap();
+    assert_eq!(
+        undone.tags.iter().collect::<Vec<_>>(),
+        before.tags.iter().collect::<Vec<_>>()
+    );
+    assert_eq!(undone.likes.value(), before.likes.value());
+
+    // Entries of a map are never removed, so adding one can't be undone
+    let map = ComposableMap::<String, PnCounter<String>>::new();
+    let mut added = ComposableMapDelta {
+        added: BTreeMap::new(),
+        updated: BTreeMap::new(),
+    };
+    added.added.insert("votes".to_string(), PnCounter::new());
+    let error = map.invert_delta(&(), &(), &added).unwrap_err();
+    assert_eq!(error.path(), ["votes".to_string()]);
+}
+
+#[test]
+fn test_invert_delta_undoes_text_edit() {
+    let mut before: Rga<char, String> = Rga::new();
+    for c in "hello".chars() {
+        before.push("alice".to_string(), c);
+    }
+    let mut after = before.clone();
+    after.delete("alice".to_string(), 0);
+    after.insert("alice".to_string(), 0, 'H');
+    after.push("alice".to_string(), '!');
+    // Typed and erased within the same edit, so there is nothing to undo
+    after.push("alice".to_string(), '?');
+    after.delete("alice".to_string(), 6);
+    assert_eq!(after.to_string(), "Hello!");
+    let delta = after.delta(&(), &(), &before.summarize(&(), &())).unwrap();
+
+    let inverse = before.invert_delta(&(), &(), &delta).unwrap();
+    assert_eq!(inverse.len(), 3);
+    let mut undone = after.clone();
+    undone
+        .apply_delta(&(), &(), &Some(inverse.clone()))
+        .unwrap();
+    assert_eq!(undone.to_string(), "hello");
+    assert!(undone.verify(&(), &()).is_ok());
+
+    // The undo reaches other peers as an ordinary delta
+    let mut bob = after.clone();
+    bob.apply_delta(&(), &(), &Some(inverse)).unwrap();
+    assert_eq!(bob, undone);
+}
+
+#[test]
+fn test_invert_delta_undoes_register_write() {
+    let before: LwwRegister<String> = LwwRegister::new("draft".to_string(), 1);
+    let mut after = before.clone();
+    after.set("final".to_string(), 5);
+    let delta = after.delta(&(), &(), &before.summarize(&(), &())).unwrap();
+
+    let inverse = before.invert_delta(&(), &(), &delta).unwrap();
+    assert_eq!(inverse.timestamp, 6);
+    after.apply_delta(&(), &(), &Some(inverse)).unwrap();
+    assert_eq!(after.get(), "draft");
+
+    // Nothing comes after the last timestamp
+    let last = LwwRegisterDelta {
+        value: "final".to_string(),
+        timestamp: u64::MAX,
+    };
+    assert!(before.invert_delta(&(), &(), &last).is_err());
+}
+
+#[test]
+fn test_built_in_types_converge() {
+    use crate::testing::{check_convergence, check_root_convergence};
+    use proptest::prelude::*;
+
+    let increments = prop::collection::vec((0u8..4, 1u64..10), 0..8);
+    check_convergence(
+        increments.prop_map(|increments| {
+            let mut counter = GCounter::<u8>::new();
+            for (actor, n) in increments {
+                counter.increment(actor, n);
+            }
+            counter
+        }),
+        &(),
+        &(),
+    );
+
+    // Additions and removals drawn from a small pool so that states overlap
+    let operations = prop::collection::vec((any::<bool>(), 0u8..4, 0u64..3), 0..8);
+    check_convergence(
+        operations.prop_map(|operations| {
+            let mut set = OrSet::<u8>::new();
+            for (add, element, nonce) in operations {
+                if add {
+                    set.add(element, nonce);
+                } else {
+                    set.remove(&element);
+                }
+            }
+            set
+        }),
+        &(),
+        &(),
+    );
+
+    let players = prop::collection::vec(("[a-c]", 1u64..5), 0..6);
+    check_root_convergence(
+        players.prop_map(|players| {
+            let mut scoreboard = Scoreboard {
+                players: GSet::new(),
+                points: GCounter::new(),
+            };
+            for (player, points) in players {
+                scoreboard.players.insert(player.clone());
+                scoreboard.points.increment(player, points);
+            }
+            scoreboard
+        }),
+        &TestStructParameters {},
+    );
+}
+
+#[test]
+#[should_panic(expected = "merge is not commutative")]
+fn test_convergence_check_catches_divergence() {
+    use crate::testing::check_convergence;
+    use proptest::prelude::*;
+
+    // The delta replaces the number, so the result depends on which side merged last
+    check_convergence(
+        (0i32..100).prop_map(ContractualI32),
+        &TestStruct::new(0, ""),
+        &TestStructParameters {},
+    );
+}
+
+#[test]
+fn test_simulated_peers_converge() {
+    use crate::sim::{NetworkConditions, Partition, Simulation};
+
+    let peers: Vec<Scoreboard> = (0..5)
+        .map(|peer| {
+            let mut scoreboard = Scoreboard {
+                players: GSet::new(),
+                points: GCounter::new(),
+            };
+            scoreboard.players.insert(format!("player {}", peer));
+            scoreboard
+                .points
+                .increment(format!("player {}", peer), peer + 1);
+            scoreboard
+        })
+        .collect();
+    let conditions = NetworkConditions {
+        drop_rate: 0.2,
+        duplicate_rate: 0.1,
+        max_delay: 3,
+        partitions: vec![Partition {
+            rounds: 0..10,
+            groups: vec![vec![0, 1], vec![2, 3, 4]],
+        }],
+    };
+    let simulate = |seed| {
+        Simulation::new(
+            peers.clone(),
+            TestStructParameters {},
+            conditions.clone(),
+            seed,
+        )
+        .assert_converges(200)
+    };
+
+    let report = simulate(7);
+    // Nothing crosses the partition before it heals
+    assert!(report.rounds > 10);
+    assert!(report.messages_dropped > 0);
+    assert!(report.bytes_sent > 0);
+    // The same seed replays the same run
+    assert_eq!(simulate(7), report);
+
+    // Messages that never arrive don't overflow the schedule
+    let mut stalled = Simulation::new(
+        peers.clone(),
+        TestStructParameters {},
+        NetworkConditions {
+            max_delay: u64::MAX,
+            ..NetworkConditions::default()
+        },
+        7,
+    );
+    assert!(!stalled.run(5).unwrap().converged);
+}
+
+#[test]
+fn test_fast_hash_vectors() {
+    use crate::util::{fast_hash, HashVersion, VersionedHash};
+
+    // Reference XXH64 outputs with seed 0
+    assert_eq!(fast_hash(b"").0 as u64, 0xEF46_DB37_51D8_E999);
+    assert_eq!(fast_hash(b"a").0 as u64, 0xD24E_C4F1_A98C_6E5B);
+    assert_eq!(fast_hash(b"abc").0 as u64, 0x44BC_2CF5_AD77_0999);
+    assert_eq!(
+        fast_hash(b"Nobody inspects the spammish repetition").0 as u64,
+        0xFBCE_A83C_8A37_8BF1
+    );
+
+    // Hashes stored by older versions can still be checked
+    let legacy = VersionedHash {
+        version: HashVersion::Polynomial,
+        hash: HashVersion::Polynomial.hash(b"abc"),
+    };
+    assert_eq!(legacy.hash, FastHash((97 * 31 + 98) * 31 + 99));
+    assert!(legacy.matches(b"abc"));
+    assert!(VersionedHash::new(b"abc").matches(b"abc"));
+    assert!(!VersionedHash::new(b"abc").matches(b"abd"));
+}
+
+#[test]
+fn test_contract_adapter_round_trip() {
+    use crate::codec::{Cbor, Codec};
+    use crate::contract::{ContractAdapter, ContractError, UpdateData, ValidateResult};
+    type Adapter = ContractAdapter<Scoreboard>;
+
+    let encode = |value: &Scoreboard| Cbor::encode(value).unwrap();
+    let parameters = encode_test_parameters();
+    let mut alice = Scoreboard {
+        players: GSet::new(),
+        points: GCounter::new(),
+    };
+    let bob = encode(&alice);
+    alice.players.insert("alice".to_string());
+    alice.points.increment("alice".to_string(), 3);
+    let alice = encode(&alice);
+
+    assert_eq!(
+        Adapter::validate_state(&parameters, &alice),
+        Ok(ValidateResult::Valid)
+    );
+    let summary = Adapter::summarize_state(&parameters, &bob).unwrap();
+    let delta = Adapter::get_state_delta(&parameters, &alice, &summary).unwrap();
+    let updated = Adapter::update_state(&parameters, &bob, &[UpdateData::Delta(&delta)]).unwrap();
+    assert_eq!(updated, alice);
+    let merged = Adapter::update_state(&parameters, &bob, &[UpdateData::State(&alice)]).unwrap();
+    assert_eq!(merged, alice);
+
+    assert!(matches!(
+        Adapter::update_state(&parameters, &bob, &[UpdateData::Delta(&[0xff])]),
+        Err(ContractError::Deser(_))
+    ));
+
+    // Parameters written as plain CBOR, without a header, are read too
+    let headerless = Cbor::encode_body(&TestStructParameters).unwrap();
+    assert_eq!(
+        Adapter::validate_state(&headerless, &alice),
+        Ok(ValidateResult::Valid)
+    );
+    assert_eq!(
+        Adapter::summarize_state(&headerless, &bob).unwrap(),
+        summary
+    );
+}
+
+#[composable]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Notice {
+    text: LwwRegister<String, u64, Notice, Vec<u8>>,
+}
+
+#[test]
+fn test_contract_adapter_headerless_parameters() {
+    use crate::codec::{Bincode, Cbor, Codec};
+    use crate::contract::{ContractAdapter, ValidateResult};
+    type Adapter = ContractAdapter<Notice>;
+
+    let notice = Notice {
+        text: LwwRegister::new("closed on Sunday".to_string(), 1),
+    };
+    let state = Cbor::encode(&notice).unwrap();
+
+    let board = vec![1u8, 2, 3];
+    for parameters in [
+        Cbor::encode_body(&board).unwrap(),
+        Cbor::encode(&board).unwrap(),
+        Bincode::encode(&board).unwrap(),
+    ] {
+        assert_eq!(
+            Adapter::validate_state(&parameters, &state),
+            Ok(ValidateResult::Valid)
+        );
+    }
+}
+
+/// `TestStructParameters` as contract parameter bytes.
+fn encode_test_parameters() -> Vec<u8> {
+    use crate::codec::{Cbor, Codec};
+    Cbor::encode(&TestStructParameters).unwrap()
+}
+
+#[test]
+fn test_codec_headers() {
+    use crate::codec::{decode_any, pretty_print, Bincode, Cbor, Codec, CodecError, Format};
+    use crate::contract::{ContractAdapter, UpdateData};
+
+    let mut scoreboard = Scoreboard {
+        players: GSet::new(),
+        points: GCounter::new(),
+    };
+    scoreboard.players.insert("alice".to_string());
+    scoreboard.points.increment("alice".to_string(), 3);
+
+    // A peer on an older build sent bincode, the CBOR adapter still reads it
+    let old_peer = Bincode::encode(&scoreboard).unwrap();
+    assert_eq!(old_peer[..2], [Format::Bincode.id(), 1]);
+    assert_eq!(decode_any::<Scoreboard>(&old_peer).unwrap(), scoreboard);
+    let parameters = encode_test_parameters();
+    let empty = Cbor::encode(&Scoreboard {
+        players: GSet::new(),
+        points: GCounter::new(),
+    })
+    .unwrap();
+    let merged = ContractAdapter::<Scoreboard>::update_state(
+        &parameters,
+        &empty,
+        &[UpdateData::State(&old_peer)],
+    )
+    .unwrap();
+    assert_eq!(merged, Cbor::encode(&scoreboard).unwrap());
+
+    assert!(pretty_print(&merged)
+        .unwrap()
+        .starts_with("CBOR (header version 1"));
+    assert!(pretty_print(&merged).unwrap().contains("alice"));
+    assert!(pretty_print(&old_peer).unwrap().starts_with("bincode"));
+
+    assert_eq!(
+        Cbor::decode::<Scoreboard>(&[0x7f, 1, 0]),
+        Err(CodecError::UnknownFormat(0x7f))
+    );
+    assert_eq!(
+        Cbor::decode::<Scoreboard>(&[Format::Cbor.id()]),
+        Err(CodecError::MissingHeader)
+    );
+    assert_eq!(
+        Cbor::decode::<Scoreboard>(&[Format::Cbor.id(), 9]),
+        Err(CodecError::UnsupportedHeaderVersion(9))
+    );
+}
+
+#[composable(version = 1)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct GuestbookV1 {
+    names: GSet<String, GuestbookV1, TestStructParameters>,
+}
+
+#[composable(version = 2, previous = "GuestbookV1")]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Guestbook {
+    names: GSet<String, Guestbook, TestStructParameters>,
+    visits: GCounter<String, Guestbook, TestStructParameters>,
+}
+
+impl crate::schema::Migrate for GuestbookV1 {
+    type Next = Guestbook;
+
+    fn migrate_state(self) -> Guestbook {
+        let mut names = GSet::new();
+        for name in self.names.iter() {
+            names.insert(name.clone());
+        }
+        Guestbook {
+            names,
+            visits: GCounter::new(),
+        }
+    }
+
+    fn migrate_summary(summary: GuestbookV1Summary) -> GuestbookSummary {
+        GuestbookSummary {
+            names: summary.names,
+            visits: BTreeMap::new(),
+        }
+    }
+
+    fn migrate_delta(delta: GuestbookV1Delta) -> GuestbookDelta {
+        GuestbookDelta {
+            names: delta.names,
+            visits: None,
+        }
+    }
+}
+
+#[test]
+fn test_versioned_state_migrates_on_load() {
+    use crate::codec::{Cbor, Codec, CodecError, Header, StateCodec};
+    use crate::contract::{ContractAdapter, UpdateData};
+    use crate::schema::{Migrating, Versioned};
+    type Old = ContractAdapter<GuestbookV1, Migrating<Cbor>>;
+    type New = ContractAdapter<Guestbook, Migrating<Cbor>>;
+    assert_eq!(Guestbook::VERSION, 2);
+
+    let parameters = encode_test_parameters();
+    let mut old = GuestbookV1 { names: GSet::new() };
+    old.names.insert("alice".to_string());
+    // Written before the state was versioned, read as version 1
+    let unversioned = Cbor::encode(&old).unwrap();
+    let state: Guestbook = Migrating::<Cbor>::decode_state(&unversioned).unwrap();
+    assert!(state.names.contains(&"alice".to_string()));
+
+    // An old peer sends its delta against a new peer's summary
+    let summary = New::summarize_state(&parameters, &Cbor::encode(&state).unwrap()).unwrap();
+    assert_eq!(Header::read(&summary).unwrap().0.schema, Some(2));
+    let old_summary = Old::summarize_state(&parameters, &unversioned).unwrap();
+    assert_eq!(Header::read(&old_summary).unwrap().0.schema, Some(1));
+    old.names.insert("bob".to_string());
+    let old_state = <Migrating<Cbor> as StateCodec<GuestbookV1>>::encode_state(&old).unwrap();
+    let old_delta = Old::get_state_delta(&parameters, &old_state, &old_summary).unwrap();
+    let updated = New::update_state(
+        &parameters,
+        &Cbor::encode(&state).unwrap(),
+        &[UpdateData::Delta(&old_delta)],
+    )
+    .unwrap();
+    let updated: Guestbook = Migrating::<Cbor>::decode_state(&updated).unwrap();
+    assert_eq!(updated.names.len(), 2);
+
+    let future = Cbor::encode_versioned(&updated, 3).unwrap();
+    assert_eq!(
+        <Migrating<Cbor> as StateCodec<Guestbook>>::decode_state(&future),
+        Err(CodecError::UnsupportedSchema(3))
+    );
+}
+
+/// A signature scheme for tests: a key signs a message with the hash of both.
+#[derive(Clone, Debug, PartialEq)]
+struct ToyScheme;
+
+impl crate::signed::Verifier for ToyScheme {
+    type PublicKey = u8;
+    type Signature = FastHash;
+
+    fn verify(key: &u8, message: &[u8], signature: &FastHash) -> bool {
+        *signature == crate::util::hash_serialized(&(key, message))
+    }
+}
+
+struct ToyKey(u8);
+
+impl crate::signed::Signer<ToyScheme> for ToyKey {
+    fn public_key(&self) -> u8 {
+        self.0
+    }
+
+    fn sign(&self, message: &[u8]) -> FastHash {
+        crate::util::hash_serialized(&(self.0, message))
+    }
+}
+
+#[test]
+fn test_signed_state_requires_authorized_signature() {
+    use crate::signed::{Signed, SignedDelta};
+    type Topic = Signed<LwwRegister<String, u64, (), Vec<u8>>, ToyScheme>;
+
+    let moderators = vec![1, 2];
+    let mut alice = Topic::new(
+        LwwRegister::new("Welcome".to_string(), 1),
+        &ToyKey(1),
+        &moderators,
+    );
+    let bob = alice.clone();
+    assert!(alice.verify(&(), &moderators).is_ok());
+    assert_eq!(
+        alice.verify(&(), &vec![2]).unwrap_err().reason(),
+        "1 is not authorized to sign"
+    );
+
+    let mut topic = alice.get().clone();
+    topic.set("Rules".to_string(), 2);
+    alice.set(topic, &ToyKey(2), &moderators);
+    let delta = alice.delta(&(), &moderators, &bob.summarize(&(), &moderators));
+    let mut updated = bob.clone();
+    updated.apply_delta(&(), &moderators, &delta).unwrap();
+    assert_eq!(updated, alice);
+
+    // A changed value no longer matches the signature, and nobody else may sign
+    let SignedDelta {
+        value, signature, ..
+    } = delta.unwrap();
+    let mut forged = value.clone().unwrap();
+    forged.value = "Spam".to_string();
+    let mut rejected = bob.clone();
+    let forged = Some(SignedDelta {
+        value: Some(forged),
+        signer: 2,
+        signature,
+    });
+    assert_eq!(
+        rejected
+            .apply_delta(&(), &moderators, &forged)
+            .unwrap_err()
+            .reason(),
+        "invalid signature"
+    );
+    let mut outsider = alice.get().clone();
+    outsider.set("Spam".to_string(), 3);
+    let outsider = Topic::new(outsider, &ToyKey(3), &moderators);
+    assert!(rejected.merge(&(), &moderators, &outsider).is_err());
+    assert_eq!(rejected, bob);
+
+    // The signature is bound to the contract's parameters, even ones naming the same keys
+    assert_eq!(
+        alice.verify(&(), &vec![2, 1]).unwrap_err().reason(),
+        "invalid signature"
+    );
+}
+
+#[test]
+fn test_signed_state_ignores_stale_deltas() {
+    use crate::signed::Signed;
+    type Topic = Signed<LwwRegister<String, u64, (), Vec<u8>>, ToyScheme>;
+
+    let moderators = vec![1];
+    let mut author = Topic::new(
+        LwwRegister::new("Welcome".to_string(), 1),
+        &ToyKey(1),
+        &moderators,
+    );
+    let reader = author.clone();
+    let summary = reader.summarize(&(), &moderators);
+    let mut deltas = Vec::new();
+    for (timestamp, topic) in [(2, "Rules"), (3, "News")] {
+        let mut register = author.get().clone();
+        register.set(topic.to_string(), timestamp);
+        author.set(register, &ToyKey(1), &moderators);
+        deltas.push(author.delta(&(), &moderators, &summary));
+    }
+
+    // Delivered newest first and then again, the older delta no longer changes the value
+    let mut updated = reader.clone();
+    for delta in deltas.iter().rev().chain(&deltas) {
+        updated.apply_delta(&(), &moderators, delta).unwrap();
+    }
+    assert_eq!(updated, author);
+    assert!(updated.verify(&(), &moderators).is_ok());
+}
+
+#[test]
+fn test_signed_state_settles_concurrent_authors() {
+    use crate::signed::Signed;
+    type Topic = Signed<LwwRegister<String, u64, (), Vec<u8>>, ToyScheme>;
+
+    let moderators = vec![1, 2];
+    let mut alice = Topic::new(
+        LwwRegister::new("Welcome".to_string(), 1),
+        &ToyKey(1),
+        &moderators,
+    );
+    let mut bob = alice.clone();
+    let summary = alice.summarize(&(), &moderators);
+    for (author, key, topic) in [(&mut alice, 1, "Rules"), (&mut bob, 2, "News")] {
+        let mut register = author.get().clone();
+        register.set(topic.to_string(), 2);
+        author.set(register, &ToyKey(key), &moderators);
+    }
+    let from_alice = alice.delta(&(), &moderators, &summary);
+    let from_bob = bob.delta(&(), &moderators, &summary);
+
+    // Whichever write wins in the register, each side takes it with its author's signature
+    alice.apply_delta(&(), &moderators, &from_bob).unwrap();
+    bob.apply_delta(&(), &moderators, &from_alice).unwrap();
+    assert_eq!(alice, bob);
+    assert!(alice.verify(&(), &moderators).is_ok());
+}
+
+#[cfg(feature = "ed25519")]
+#[test]
+fn test_ed25519_signed_state() {
+    use crate::signed::{Ed25519, FromParentState, Signed};
+    use ed25519_dalek::{SigningKey, VerifyingKey};
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    struct Members(Vec<VerifyingKey>);
+
+    impl crate::signed::AuthorizedKeys<VerifyingKey> for Members {
+        fn is_authorized(&self, key: &VerifyingKey) -> bool {
+            self.0.contains(key)
+        }
+    }
+
+    let member = SigningKey::from_bytes(&[7; 32]);
+    let stranger = SigningKey::from_bytes(&[8; 32]);
+    let members = Members(vec![member.verifying_key()]);
+    let nickname: Signed<LwwRegister<String, u64, Members>, Ed25519, FromParentState> =
+        Signed::new(LwwRegister::new("alice".to_string(), 1), &member, &());
+    assert!(nickname.verify(&members, &()).is_ok());
+    let forged: Signed<_, Ed25519, FromParentState> =
+        Signed::new(nickname.get().clone(), &stranger, &());
+    assert!(forged.verify(&members, &()).is_err());
+}
+
+#[test]
+fn test_owner_config_only_accepts_newer_versions_from_owner() {
+    use crate::config::OwnerConfig;
+    // The parameters are the owner's key
+    type Settings = OwnerConfig<BTreeMap<String, String>, ToyScheme, (), u8>;
+
+    let owner = ToyKey(1);
+    let mut latest = Settings::new(BTreeMap::new(), &owner, &1);
+    let mut peer = latest.clone();
+    assert!(latest.verify(&(), &1).is_ok());
+    assert!(latest.verify(&(), &2).is_err());
+
+    let mut settings = BTreeMap::new();
+    settings.insert("title".to_string(), "Freenet".to_string());
+    latest.update(settings, &owner, &1).unwrap();
+    assert_eq!(latest.summarize(&(), &1).0, 1);
+    let delta = latest.delta(&(), &1, &peer.summarize(&(), &1));
+    peer.apply_delta(&(), &1, &delta).unwrap();
+    assert_eq!(peer, latest);
+    // Duplicated and replayed deltas are ignored
+    peer.apply_delta(&(), &1, &delta).unwrap();
+    assert_eq!(peer, latest);
+    let first = Settings::new(BTreeMap::new(), &owner, &1);
+    let replayed = first.delta(&(), &1, &(0, FastHash(i64::MAX)));
+    assert!(replayed.is_some());
+    peer.apply_delta(&(), &1, &replayed).unwrap();
+    assert_eq!(peer, latest);
+
+    let mut hijacked = latest.clone();
+    hijacked.update(BTreeMap::new(), &ToyKey(2), &1).unwrap();
+    let delta = hijacked.delta(&(), &1, &peer.summarize(&(), &1));
+    assert_eq!(
+        peer.apply_delta(&(), &1, &delta).unwrap_err().reason(),
+        "version 2 is not signed by the owner"
+    );
+    assert_eq!(peer, latest);
+}
+
+#[test]
+fn test_owner_config_settles_configs_with_the_same_version() {
+    use crate::config::OwnerConfig;
+    type Settings = OwnerConfig<String, ToyScheme, (), u8>;
+
+    // The owner edits from two devices that both start from version 0
+    let owner = ToyKey(1);
+    let mut laptop = Settings::new("light".to_string(), &owner, &1);
+    let mut phone = laptop.clone();
+    laptop.update("dark".to_string(), &owner, &1).unwrap();
+    phone.update("solarized".to_string(), &owner, &1).unwrap();
+
+    let mut left = laptop.clone();
+    left.merge(&(), &1, &phone).unwrap();
+    let mut right = phone.clone();
+    right.merge(&(), &1, &laptop).unwrap();
+    assert_eq!(left, right);
+    assert_eq!(left.version(), 1);
+
+    // A state can claim any version, but none past the last one
+    let mut value = ciborium::Value::serialized(&left).unwrap();
+    for (key, field) in value.as_map_mut().unwrap() {
+        if key.as_text() == Some("version") {
+            *field = ciborium::Value::from(u64::MAX);
+        }
+    }
+    let mut exhausted: Settings = value.deserialized().unwrap();
+    assert!(exhausted.update("dark".to_string(), &owner, &1).is_err());
+    assert_eq!(exhausted.version(), u64::MAX);
+}
@@ -1,12 +1,93 @@
+use crate::ComposableState;
 use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh64::xxh64;
 
+/// The seed of [`HashVersion::Xxh64`], fixed so every peer computes the same hashes.
+pub const XXH64_SEED: u64 = 0;
+
+/// The algorithms [`fast_hash`] has used, so hashes stored by older versions can still be
+/// checked.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Clone, Debug, Ord, PartialOrd, Copy)]
+pub enum HashVersion {
+    /// The original 31-multiplier polynomial, which collides easily. Only for checking hashes
+    /// computed before it was replaced.
+    Polynomial = 0,
+    /// XXH64 with [`XXH64_SEED`].
+    Xxh64 = 1,
+}
+
+impl HashVersion {
+    /// The algorithm used by [`fast_hash`].
+    pub const CURRENT: HashVersion = HashVersion::Xxh64;
+
+    pub fn hash(self, bytes: &[u8]) -> FastHash {
+        match self {
+            HashVersion::Polynomial => {
+                let mut hash: i64 = 0;
+                for &byte in bytes {
+                    hash = hash.wrapping_mul(31).wrapping_add(byte as i64);
+                }
+                FastHash(hash)
+            }
+            // The same on every platform, WASM included, since XXH64 reads its input as
+            // little-endian words whatever the native byte order
+            HashVersion::Xxh64 => FastHash(xxh64(bytes, XXH64_SEED) as i64),
+        }
+    }
+}
+
+/// Hashes `bytes` with [`HashVersion::CURRENT`].
 pub fn fast_hash(bytes: &[u8]) -> FastHash {
-    let mut hash: i64 = 0;
-    for &byte in bytes {
-        hash = hash.wrapping_mul(31).wrapping_add(byte as i64);
+    HashVersion::CURRENT.hash(bytes)
+}
+
+/// A hash along with the algorithm that computed it, for hashes that are stored or sent to
+/// other peers and have to remain checkable after [`HashVersion::CURRENT`] changes.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Clone, Debug, Ord, PartialOrd, Copy)]
+pub struct VersionedHash {
+    pub version: HashVersion,
+    pub hash: FastHash,
+}
+
+impl VersionedHash {
+    /// Hashes `bytes` with [`HashVersion::CURRENT`].
+    pub fn new(bytes: &[u8]) -> Self {
+        VersionedHash {
+            version: HashVersion::CURRENT,
+            hash: fast_hash(bytes),
+        }
+    }
+
+    /// Whether `bytes` hash to this hash with the algorithm that computed it.
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        self.version.hash(bytes) == self.hash
+    }
+}
+
+/// Hashes the bincode serialization of `value`, which is the same on every platform. Types
+/// containing a `HashMap` or `HashSet` serialize in iteration order and shouldn't be hashed
+/// this way.
+///
+/// Panics if `value` cannot be serialized, which only happens for types whose `Serialize`
+/// implementation fails or that serialize sequences without a known length.
+pub fn hash_serialized<T: Serialize + ?Sized>(value: &T) -> FastHash {
+    let bytes = bincode::serialize(value).expect("value should be serializable");
+    fast_hash(&bytes)
+}
+
+/// [`ComposableState::compose_deltas`] for the optional deltas of a field: a missing delta
+/// leaves the other one unchanged. Returns `None` if both are present and can't be combined.
+pub fn compose_optional_deltas<T: ComposableState>(
+    first: &Option<T::Delta>,
+    second: &Option<T::Delta>,
+) -> Option<Option<T::Delta>> {
+    match (first, second) {
+        (Some(first), Some(second)) => T::compose_deltas(first, second).map(Some),
+        (first, None) => Some(first.clone()),
+        (None, second) => Some(second.clone()),
     }
-    FastHash(hash)
 }
 
+/// A 64-bit hash, see [`fast_hash`]. The XXH64 output is stored as `i64`, bit for bit.
 #[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Clone, Debug, Ord, PartialOrd, Copy)]
 pub struct FastHash(pub i64);
@@ -0,0 +1,94 @@
+use crate::ScaffoldError;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// A vector clock with one counter per actor.
+///
+/// Counters that are zero are never stored, so two vectors that compare equal also serialize
+/// identically. Zero counters in deserialized vectors are dropped.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(from = "RawVersionVector<K>")]
+#[serde(bound(deserialize = "K: Deserialize<'de>"))]
+pub struct VersionVector<K: Ord>(BTreeMap<K, u64>);
+
+/// The serialized form of a [`VersionVector`], which may contain zero counters.
+#[derive(Deserialize)]
+struct RawVersionVector<K: Ord>(BTreeMap<K, u64>);
+
+impl<K: Ord> From<RawVersionVector<K>> for VersionVector<K> {
+    fn from(RawVersionVector(mut counters): RawVersionVector<K>) -> Self {
+        counters.retain(|_, counter| *counter > 0);
+        VersionVector(counters)
+    }
+}
+
+impl<K: Ord> Default for VersionVector<K> {
+    fn default() -> Self {
+        VersionVector(BTreeMap::new())
+    }
+}
+
+impl<K: Ord + Clone> VersionVector<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The counter for `actor`, zero if it has never been incremented.
+    pub fn get(&self, actor: &K) -> u64 {
+        self.0.get(actor).copied().unwrap_or(0)
+    }
+
+    /// Increments the counter for `actor` and returns its new value. Fails if the counter is
+    /// already `u64::MAX`, which only a malicious peer can reach, since a write that doesn't
+    /// advance the vector would be mistaken for one that has already been seen.
+    pub fn increment(&mut self, actor: K) -> Result<u64, ScaffoldError> {
+        let counter = self.0.entry(actor).or_insert(0);
+        *counter = counter.checked_add(1).ok_or_else(|| {
+            ScaffoldError::limit_exceeded("a version vector counter has reached u64::MAX")
+        })?;
+        Ok(*counter)
+    }
+
+    /// Whether any counter has reached `u64::MAX`, so that its actor can't write again.
+    pub fn is_exhausted(&self) -> bool {
+        self.0.values().any(|counter| *counter == u64::MAX)
+    }
+
+    /// Raises the counter for `actor` to `counter` if it is lower.
+    pub fn observe(&mut self, actor: K, counter: u64) {
+        if counter > 0 {
+            let current = self.0.entry(actor).or_insert(0);
+            *current = (*current).max(counter);
+        }
+    }
+
+    /// Takes the per-actor maximum of both vectors.
+    pub fn join(&mut self, other: &VersionVector<K>) {
+        for (actor, counter) in &other.0 {
+            self.observe(actor.clone(), *counter);
+        }
+    }
+
+    /// Whether every counter in `other` is less than or equal to the one in `self`.
+    pub fn dominates(&self, other: &VersionVector<K>) -> bool {
+        other
+            .0
+            .iter()
+            .all(|(actor, counter)| self.get(actor) >= *counter)
+    }
+
+    /// Orders the vectors by causality, `None` if they are concurrent.
+    pub fn causal_cmp(&self, other: &VersionVector<K>) -> Option<Ordering> {
+        match (self.dominates(other), other.dominates(self)) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Greater),
+            (false, true) => Some(Ordering::Less),
+            (false, false) => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, u64)> {
+        self.0.iter().map(|(actor, counter)| (actor, *counter))
+    }
+}